@@ -0,0 +1,227 @@
+pub mod ao;
+pub mod aov;
+pub mod bdpt;
+pub mod mis;
+pub mod naive;
+pub mod nee;
+pub mod photon;
+pub mod spectral;
+
+extern crate rand;
+use rand::Rng;
+
+use std::f32;
+use std::ops::{AddAssign, Mul};
+
+use crate::math::ray::Ray;
+use crate::math::vec3::*;
+use crate::world::hitable::{HitRecord, Hitable};
+use crate::world::materials::Material;
+use crate::world::scene::Scene;
+use crate::world::subsurface::Medium;
+
+/// Light deposited at film coordinates (s, t) by a path that did not start at that pixel.
+pub struct Splat {
+    pub s: f32,
+    pub t: f32,
+    pub value: Vec3,
+}
+
+/// Estimates the radiance arriving at the camera along a ray. Integrators that trace paths
+/// from the lights may also push contributions to arbitrary pixels onto `splats`; the film
+/// adds those up and divides them by the samples per pixel like everything else.
+pub trait Integrator: Send+Sync {
+    fn li(&self, r: &Ray, scene: &Scene, splats: &mut Vec<Splat>) -> Vec3;
+
+    /// Called before each sweep over the film, for integrators that precompute per pass.
+    fn begin_pass(&self, _scene: &Scene, _pass: usize) {
+    }
+
+    /// Whether the integrator changes between passes, so the film must be swept once per
+    /// sample with `begin_pass` in between rather than taking every sample in one sweep.
+    fn progressive(&self) -> bool {
+        false
+    }
+
+    /// Whether paths follow `Material::medium` into the insides of surfaces. Scenes with
+    /// media must not be rendered with integrators that don't, or subsurface materials turn
+    /// into glass on the way in and diffuse surfaces on the way out.
+    fn supports_media(&self) -> bool {
+        true
+    }
+
+    /// Linear RGB for a pixel, from the average of what `li` and the splats gave it. Only
+    /// integrators that gather light in some other color space need to convert.
+    fn to_rgb(&self, value: Vec3) -> Vec3 {
+        value
+    }
+}
+
+/// What a path carries light in, so light sampling can be shared between integrators that
+/// trace RGB and ones that trace a few wavelengths. Materials, lights and the background
+/// are RGB and get lifted into it where a path meets them.
+pub trait Radiance {
+    type Value: Copy + AddAssign + Mul<Output = Self::Value> + Mul<f32, Output = Self::Value>;
+
+    fn lift(&self, rgb: Vec3) -> Self::Value;
+
+    /// `ray` set up to carry this radiance, e.g. at the right wavelength.
+    fn trace(&self, ray: Ray) -> Ray {
+        ray
+    }
+}
+
+/// Plain RGB radiance.
+pub struct Rgb;
+
+impl Radiance for Rgb {
+    type Value = Vec3;
+
+    fn lift(&self, rgb: Vec3) -> Vec3 {
+        rgb
+    }
+}
+
+/// Medium a path is travelling through, if it is inside a `Subsurface` or similar.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct PathMedium {
+    medium: Option<Medium>
+}
+
+impl PathMedium {
+    /// Flies `ray` through the current medium, if any, towards the surface it hits at `t` or,
+    /// with `hit` unset, towards infinity. Returns the ray leaving a scattering event on the
+    /// way, or `None` if the path reaches the surface. Either way `throughput` is updated.
+    /// Light sampling can't see through the boundary, so paths scattered inside must count
+    /// the emission they hit next as if they had bounced specularly.
+    pub fn scatter(&self, ray: &Ray, hit: bool, t: f32, throughput: &mut Vec3) -> Option<Ray> {
+        self.medium.and_then(|medium| medium.sample(ray, if hit { t } else { f32::MAX }, throughput))
+    }
+
+    /// Enters or leaves the medium of `material` after it scattered the path at `rec`
+    /// towards `direction`.
+    pub fn cross(&mut self, material: &dyn Material, rec: &HitRecord, direction: Vec3) {
+        if let Some(inside) = material.medium() {
+            self.medium = if dot(direction, rec.normal) < 0.0 { Some(inside) } else { None };
+        }
+    }
+}
+
+/// How long the path tracers keep bouncing.
+#[derive(Debug, Copy, Clone)]
+pub struct PathLength {
+    /// Bounces always taken before Russian roulette may end a path.
+    pub min_depth: u32,
+    /// Hard limit on bounces. Cutting paths off loses their light, so leave it `None`
+    /// for unbiased results and rely on Russian roulette.
+    pub max_depth: Option<u32>,
+}
+
+impl PathLength {
+    pub fn new(min_depth: u32, max_depth: Option<u32>) -> Self {
+        PathLength {
+            min_depth,
+            max_depth
+        }
+    }
+
+    /// Decides whether a path that has made `depth` bounces goes on. Past `min_depth` it
+    /// survives with a probability that follows its throughput, and the survivors are
+    /// reweighted so the estimate stays unbiased.
+    pub fn continue_path(&self, depth: u32, throughput: &mut Vec3) -> bool {
+        match self.survival(depth, throughput.x().max(throughput.y()).max(throughput.z())) {
+            Some(survival) => {
+                *throughput /= survival;
+                true
+            },
+            None => false
+        }
+    }
+
+    /// The decision of `continue_path` for a path whose largest throughput component is
+    /// `max_throughput`: `None` to stop, or the probability it survived with, which the
+    /// throughput must be divided by.
+    pub fn survival(&self, depth: u32, max_throughput: f32) -> Option<f32> {
+        if let Some(max_depth) = self.max_depth {
+            if depth >= max_depth {
+                return None;
+            }
+        }
+        if depth < self.min_depth {
+            return Some(1.0);
+        }
+        let survival = max_throughput.min(0.95);
+        if survival <= 0.0 || rand::thread_rng().gen::<f32>() >= survival {
+            return None;
+        }
+        Some(survival)
+    }
+}
+
+impl Default for PathLength {
+    fn default() -> Self {
+        PathLength::new(3, None)
+    }
+}
+
+/// Veach's power heuristic (beta = 2) weight for a sample drawn with density `f`,
+/// when another strategy could have drawn it with density `g`.
+pub fn power_heuristic(f: f32, g: f32) -> f32 {
+    let (f2, g2) = (f * f, g * g);
+    if f2 + g2 <= 0.0 {
+        return 0.0;
+    }
+    f2 / (f2 + g2)
+}
+
+/// Light sample weighted against the chance of the BSDF picking the same direction.
+pub fn sample_lights<R: Radiance>(r: &Ray, rec: &HitRecord, material: &dyn Material, scene: &Scene, radiance: &R) -> R::Value {
+    let zero = radiance.lift(Vec3::zero());
+    if scene.lights.is_empty() {
+        return zero;
+    }
+    let wi = unit_vector(scene.lights.random(rec.p));
+    let light_pdf = scene.lights.pdf_value(rec.p, wi);
+    if light_pdf <= 0.0 {
+        return zero;
+    }
+    let wo = -unit_vector(r.direction());
+    let f = material.eval(wo, wi, rec);
+    if f.x() <= 0.0 && f.y() <= 0.0 && f.z() <= 0.0 {
+        return zero;
+    }
+    let shadow_ray = radiance.trace(Ray::new(rec.p, wi));
+    let mut light_rec = HitRecord::new();
+    if !scene.world.hit(&shadow_ray, 0.001, f32::MAX, &mut light_rec) {
+        return zero;
+    }
+    match light_rec.material {
+        Some(ref light) => {
+            let weight = power_heuristic(light_pdf, material.pdf(wo, wi, rec));
+            radiance.lift(f) * radiance.lift(light.emitted(&shadow_ray, &light_rec))
+                * (weight * dot(wi, rec.normal).abs() / light_pdf)
+        },
+        None => zero
+    }
+}
+
+/// Direct light from every punctual light in the scene. These can't be hit by BSDF samples,
+/// so there is nothing to weigh them against.
+pub fn sample_punctual_lights<R: Radiance>(r: &Ray, rec: &HitRecord, material: &dyn Material, scene: &Scene, radiance: &R) -> R::Value {
+    let wo = -unit_vector(r.direction());
+    let mut total = radiance.lift(Vec3::zero());
+    for light in scene.punctual_lights.iter() {
+        let sample = light.sample(rec.p);
+        if sample.radiance.x() <= 0.0 && sample.radiance.y() <= 0.0 && sample.radiance.z() <= 0.0 {
+            continue;
+        }
+        let f = material.eval(wo, sample.wi, rec);
+        if f.x() <= 0.0 && f.y() <= 0.0 && f.z() <= 0.0 {
+            continue;
+        }
+        if scene.unoccluded(rec.p, sample.wi, sample.distance) {
+            total += radiance.lift(f) * radiance.lift(sample.radiance) * dot(sample.wi, rec.normal).abs();
+        }
+    }
+    total
+}
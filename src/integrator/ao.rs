@@ -0,0 +1,45 @@
+use std::f32;
+
+use crate::math::onb::Onb;
+use crate::math::ray::Ray;
+use crate::math::vec3::*;
+use crate::world::hitable::*;
+use crate::world::materials::facing_normal;
+use crate::world::scene::Scene;
+use super::*;
+
+/// Ambient occlusion: the fraction of `samples` cosine-weighted rays from the first hit that
+/// travel `max_distance` without hitting anything. Rays that miss the scene count as fully open.
+/// Quick to converge, so handy for previews and for baking.
+pub struct AmbientOcclusion {
+    samples: u32,
+    max_distance: f32
+}
+
+impl AmbientOcclusion {
+    pub fn new(samples: u32, max_distance: f32) -> Self {
+        AmbientOcclusion {
+            samples,
+            max_distance
+        }
+    }
+}
+
+impl Integrator for AmbientOcclusion {
+    fn li(&self, r: &Ray, scene: &Scene, _splats: &mut Vec<Splat>) -> Vec3 {
+        let mut rec = HitRecord::new();
+        if !scene.world.hit(r, 0.001, f32::MAX, &mut rec) {
+            return Vec3::one();
+        }
+        let frame = Onb::from_w(facing_normal(-r.direction(), &rec));
+        let mut open = 0;
+        for _ in 0..self.samples {
+            let direction = frame.local(random_cosine_direction());
+            if !scene.world.occluded(&Ray::new(rec.p, direction), 0.001, self.max_distance) {
+                open += 1;
+            }
+        }
+        let fraction = open as f32 / self.samples.max(1) as f32;
+        Vec3::new(fraction, fraction, fraction)
+    }
+}
@@ -0,0 +1,100 @@
+use std::f32;
+
+#[cfg(feature = "bvh-stats")]
+use crate::math::aabb::hit_tests;
+use crate::math::ray::Ray;
+use crate::math::vec3::*;
+use crate::world::hitable::*;
+use crate::world::scene::Scene;
+use super::*;
+
+/// What an `Aov` shows of the first surface a camera ray hits.
+#[derive(Debug, Copy, Clone)]
+pub enum AovKind {
+    /// Shading normal mapped from [-1, 1] to [0, 1] per axis.
+    ShadingNormal,
+    /// Geometric normal, mapped the same way; differs from the shading normal on smooth meshes.
+    GeometricNormal,
+    /// Distance along the ray, black at the camera and white at the given distance and beyond.
+    Depth(f32),
+    /// Texture coordinates as red and green, wrapped to [0, 1).
+    Uv,
+    /// A false color per material, so surfaces sharing a material share a color.
+    MaterialId,
+    /// Bounding box tests the closest hit query took, from blue through green to red at the
+    /// given count. Misses are costed too. Needs the `bvh-stats` feature.
+    #[cfg(feature = "bvh-stats")]
+    BvhCost(u32),
+}
+
+/// Diagnostic view of the scene instead of an estimate of its radiance. Values are squared on
+/// the way out so they reach the image unchanged through the film's gamma. Rays that hit nothing
+/// are black.
+pub struct Aov {
+    kind: AovKind
+}
+
+impl Aov {
+    pub fn new(kind: AovKind) -> Self {
+        Aov {
+            kind
+        }
+    }
+}
+
+impl Integrator for Aov {
+    fn li(&self, r: &Ray, scene: &Scene, _splats: &mut Vec<Splat>) -> Vec3 {
+        let mut rec = HitRecord::new();
+        #[cfg(feature = "bvh-stats")]
+        let tests = hit_tests();
+        let hit = scene.world.hit(r, 0.001, f32::MAX, &mut rec);
+        let value = match self.kind {
+            #[cfg(feature = "bvh-stats")]
+            AovKind::BvhCost(max_tests) => heat((hit_tests() - tests) as f32 / max_tests.max(1) as f32),
+            _ if !hit => Vec3::zero(),
+            AovKind::ShadingNormal => 0.5 * (rec.normal + Vec3::one()),
+            AovKind::GeometricNormal => 0.5 * (rec.geometric_normal + Vec3::one()),
+            AovKind::Depth(far) => {
+                let depth = (rec.t * r.direction().length() / far).min(1.0);
+                Vec3::new(depth, depth, depth)
+            },
+            AovKind::Uv => Vec3::new(rec.u - rec.u.floor(), rec.v - rec.v.floor(), 0.0),
+            AovKind::MaterialId => match rec.material {
+                Some(ref material) => scene.material_id(material).map_or(Vec3::one(), false_color),
+                None => Vec3::zero()
+            },
+        };
+        value * value
+    }
+}
+
+/// Blue to green to red as `x` goes from 0 to 1.
+#[cfg(feature = "bvh-stats")]
+fn heat(x: f32) -> Vec3 {
+    let x = x.clamp(0.0, 1.0);
+    if x < 0.5 {
+        Vec3::new(0.0, 2.0 * x, 1.0 - 2.0 * x)
+    }
+    else {
+        Vec3::new(2.0 * x - 1.0, 2.0 - 2.0 * x, 0.0)
+    }
+}
+
+/// Saturated color with a hue hashed from `id`, so nearby ids look different.
+fn false_color(id: usize) -> Vec3 {
+    // SplitMix64's finalizer: every input bit affects the top bits.
+    let mut hash = (id as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^= hash >> 31;
+    let hue = (hash >> 40) as f32 / (1u64 << 24) as f32 * 6.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    match hue as u32 {
+        0 => Vec3::new(1.0, x, 0.0),
+        1 => Vec3::new(x, 1.0, 0.0),
+        2 => Vec3::new(0.0, 1.0, x),
+        3 => Vec3::new(0.0, x, 1.0),
+        4 => Vec3::new(x, 0.0, 1.0),
+        _ => Vec3::new(1.0, 0.0, x)
+    }
+}
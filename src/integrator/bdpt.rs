@@ -0,0 +1,385 @@
+use std::f32;
+use std::sync::Arc;
+
+use crate::math::onb::Onb;
+use crate::math::ray::Ray;
+use crate::math::vec3::*;
+use crate::world::hitable::*;
+use crate::world::materials::Material;
+use crate::world::scene::Scene;
+use super::*;
+
+/// Bidirectional path tracer (Veach 1997). Each camera sample traces a subpath from the camera
+/// and one from a point on a light, then joins every prefix of one to every prefix of the other,
+/// weighting each strategy with the balance heuristic. Joining light subpaths straight to the
+/// lens splats onto other pixels; that is what resolves caustics seen directly.
+///
+/// Only lights in the scene's light list start subpaths. Punctual lights are added as plain
+/// direct lighting at camera vertices, and materials that report specular samples are treated
+/// as delta lobes that paths can pass through but never connect at.
+pub struct Bidirectional {
+    length: PathLength
+}
+
+impl Bidirectional {
+    pub fn new(length: PathLength) -> Self {
+        Bidirectional {
+            length
+        }
+    }
+}
+
+impl Default for Bidirectional {
+    fn default() -> Self {
+        Bidirectional::new(PathLength::default())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum VertexKind {
+    Camera,
+    Light,
+    Surface,
+}
+
+/// A path vertex. Densities are per unit area: `pdf_fwd` of reaching this vertex the way the
+/// subpath did, `pdf_rev` of reaching it from the other end.
+#[derive(Clone)]
+struct Vertex {
+    kind: VertexKind,
+    rec: HitRecord,
+    /// Unit direction back towards the previous vertex of the subpath; zero at endpoints.
+    wo: Vec3,
+    beta: Vec3,
+    delta: bool,
+    pdf_fwd: f32,
+    pdf_rev: f32,
+    /// Area density of the light sampler starting a path here, zero off the light list.
+    light_pdf: f32,
+}
+
+impl Vertex {
+    fn endpoint(kind: VertexKind, rec: HitRecord, beta: Vec3, pdf_fwd: f32) -> Self {
+        Vertex {
+            kind,
+            rec,
+            wo: Vec3::zero(),
+            beta,
+            delta: false,
+            pdf_fwd,
+            pdf_rev: 0.0,
+            light_pdf: 0.0
+        }
+    }
+
+    fn p(&self) -> Vec3 {
+        self.rec.p
+    }
+
+    fn material(&self) -> Option<&Arc<dyn Material>> {
+        self.rec.material.as_ref()
+    }
+
+    /// Converts a solid angle density at `self` towards `next` into an area density at `next`.
+    fn convert_density(&self, pdf: f32, next: &Vertex) -> f32 {
+        let d = next.p() - self.p();
+        let distance_squared = d.squared_length();
+        if distance_squared <= 0.0 {
+            return 0.0;
+        }
+        let cosine = if next.kind == VertexKind::Camera { 1.0 } else { dot(next.rec.normal, d).abs() / distance_squared.sqrt() };
+        pdf * cosine / distance_squared
+    }
+
+    /// Area density of this vertex emitting towards `next` when it starts a light subpath.
+    fn pdf_light(&self, next: &Vertex) -> f32 {
+        let cosine = dot(unit_vector(next.p() - self.p()), self.rec.normal);
+        self.convert_density(cosine.max(0.0) / f32::consts::PI, next)
+    }
+
+    /// Area density of sampling `next` from this vertex, having arrived from `prev`.
+    fn pdf(&self, scene: &Scene, prev: Option<&Vertex>, next: &Vertex) -> f32 {
+        match self.kind {
+            VertexKind::Camera => self.convert_density(scene.camera.pdf_direction(self.p(), next.p()), next),
+            VertexKind::Light => self.pdf_light(next),
+            VertexKind::Surface => {
+                let (prev, material) = match (prev, self.material()) {
+                    (Some(prev), Some(material)) => (prev, material),
+                    _ => return 0.0
+                };
+                let wo = unit_vector(prev.p() - self.p());
+                let wi = unit_vector(next.p() - self.p());
+                self.convert_density(material.pdf(wo, wi, &self.rec), next)
+            }
+        }
+    }
+
+    /// Radiance this vertex gives off towards `p`, for vertices on emitters.
+    fn le(&self, p: Vec3) -> Vec3 {
+        match self.material() {
+            Some(material) => {
+                let d = unit_vector(self.p() - p);
+                material.emitted(&Ray::new(self.p() - d, d), &self.rec)
+            },
+            None => Vec3::zero()
+        }
+    }
+
+    /// Scattering at a surface vertex towards `p`, with light arriving from its other side of
+    /// the path; the light's own emission for a light endpoint.
+    fn f(&self, p: Vec3, towards_camera: bool) -> Vec3 {
+        match self.kind {
+            VertexKind::Light => self.le(p),
+            VertexKind::Camera => Vec3::zero(),
+            VertexKind::Surface => match self.material() {
+                Some(material) => {
+                    let w = unit_vector(p - self.p());
+                    if towards_camera {
+                        material.eval(w, self.wo, &self.rec)
+                    }
+                    else {
+                        material.eval(self.wo, w, &self.rec)
+                    }
+                },
+                None => Vec3::zero()
+            }
+        }
+    }
+}
+
+fn is_black(c: Vec3) -> bool {
+    c.x() <= 0.0 && c.y() <= 0.0 && c.z() <= 0.0
+}
+
+impl Bidirectional {
+    /// Extends `path` from its last vertex along `ray`, whose direction was sampled with solid
+    /// angle density `pdf`. Returns the ray that left the scene, if the walk ended that way.
+    fn random_walk(&self, scene: &Scene, mut ray: Ray, mut beta: Vec3, mut pdf: f32, path: &mut Vec<Vertex>) -> Option<Ray> {
+        loop {
+            let mut rec = HitRecord::new();
+            if !scene.world.hit(&ray, 0.001, f32::MAX, &mut rec) {
+                return Some(ray);
+            }
+            let material = match rec.material {
+                Some(ref material) => Arc::clone(material),
+                None => return None
+            };
+            let wo = -unit_vector(ray.direction());
+            let light_pdf = if material.is_emissive() { scene.lights.surface_pdf(&ray, rec.t) } else { 0.0 };
+            let mut vertex = Vertex {
+                kind: VertexKind::Surface,
+                rec,
+                wo,
+                beta,
+                delta: false,
+                pdf_fwd: 0.0,
+                pdf_rev: 0.0,
+                light_pdf
+            };
+            vertex.pdf_fwd = path[path.len() - 1].convert_density(pdf, &vertex);
+            let srec = material.sample(&ray, &vertex.rec);
+            let srec = match srec {
+                Some(srec) => srec,
+                None => {
+                    path.push(vertex);
+                    return None;
+                }
+            };
+            let wi = unit_vector(srec.scattered.direction());
+            let pdf_rev = if srec.is_specular {
+                pdf = 0.0;
+                0.0
+            }
+            else {
+                pdf = srec.pdf;
+                material.pdf(wi, wo, &vertex.rec)
+            };
+            vertex.delta = srec.is_specular;
+            let previous = path.len() - 1;
+            path[previous].pdf_rev = vertex.convert_density(pdf_rev, &path[previous]);
+            path.push(vertex);
+
+            beta = beta * srec.attenuation;
+            if !self.length.continue_path(path.len() as u32 - 1, &mut beta) {
+                return None;
+            }
+            ray = srec.scattered;
+        }
+    }
+
+    /// Balance heuristic weight of joining `light[..s]` and `camera[..t]`, found by walking
+    /// along the path and comparing the density of each other strategy with this one.
+    fn mis_weight(&self, scene: &Scene, light: &[Vertex], camera: &[Vertex], s: usize, t: usize) -> f32 {
+        if s + t == 2 {
+            return 1.0;
+        }
+        let qs = if s > 0 { Some(&light[s - 1]) } else { None };
+        let pt = &camera[t - 1];
+        let qs_minus = if s > 1 { Some(&light[s - 2]) } else { None };
+        let pt_minus = if t > 1 { Some(&camera[t - 2]) } else { None };
+        if s == 0 && pt.light_pdf <= 0.0 {
+            // Nothing but a camera path can find an emitter that isn't on the light list.
+            return 1.0;
+        }
+
+        // (pdf_fwd, pdf_rev, delta) of each vertex, as they would be for this strategy.
+        let mut cam: Vec<(f32, f32, bool)> = camera[..t].iter().map(|v| (v.pdf_fwd, v.pdf_rev, v.delta)).collect();
+        let mut lig: Vec<(f32, f32, bool)> = light[..s].iter().map(|v| (v.pdf_fwd, v.pdf_rev, v.delta)).collect();
+        cam[t - 1].2 = false;
+        if let Some(qs) = qs {
+            lig[s - 1].2 = false;
+            cam[t - 1].1 = qs.pdf(scene, qs_minus, pt);
+            lig[s - 1].1 = pt.pdf(scene, pt_minus, qs);
+            if let Some(pt_minus) = pt_minus {
+                cam[t - 2].1 = pt.pdf(scene, Some(qs), pt_minus);
+            }
+            if let Some(qs_minus) = qs_minus {
+                lig[s - 2].1 = qs.pdf(scene, Some(pt), qs_minus);
+            }
+        }
+        else {
+            cam[t - 1].1 = pt.light_pdf;
+            if let Some(pt_minus) = pt_minus {
+                cam[t - 2].1 = pt.pdf_light(pt_minus);
+            }
+        }
+
+        // Delta vertices have no density; treat it as one so ratios pass straight through them.
+        let remap = |f: f32| if f != 0.0 { f } else { 1.0 };
+        let mut sum = 0.0;
+        let mut ri = 1.0;
+        for i in (1..t).rev() {
+            ri *= remap(cam[i].1) / remap(cam[i].0);
+            if !cam[i].2 && !cam[i - 1].2 {
+                sum += ri;
+            }
+        }
+        ri = 1.0;
+        for i in (0..s).rev() {
+            ri *= remap(lig[i].1) / remap(lig[i].0);
+            let delta_before = i > 0 && lig[i - 1].2;
+            if !lig[i].2 && !delta_before {
+                sum += ri;
+            }
+        }
+        1.0 / (1.0 + sum)
+    }
+
+    /// Unweighted contribution of joining `light[..s]` to `camera[..t]`, for t >= 2.
+    fn connect(&self, scene: &Scene, light: &[Vertex], camera: &[Vertex], s: usize, t: usize) -> Vec3 {
+        let pt = &camera[t - 1];
+        if s == 0 {
+            return pt.beta * pt.le(camera[t - 2].p());
+        }
+        let qs = &light[s - 1];
+        if pt.delta || qs.delta {
+            return Vec3::zero();
+        }
+        let f = pt.f(qs.p(), false) * qs.f(pt.p(), true);
+        if is_black(f) {
+            return Vec3::zero();
+        }
+        let d = qs.p() - pt.p();
+        let distance = d.length();
+        let wi = d / distance;
+        if !scene.unoccluded(pt.p(), wi, distance) {
+            return Vec3::zero();
+        }
+        let g = dot(wi, pt.rec.normal).abs() * dot(wi, qs.rec.normal).abs() / (distance * distance);
+        pt.beta * f * qs.beta * g
+    }
+
+    /// Joins `light[..s]` straight to the lens at `camera[0]`, splatting onto whichever pixel it lands.
+    fn splat(&self, scene: &Scene, light: &[Vertex], camera: &[Vertex], s: usize, splats: &mut Vec<Splat>) {
+        let qs = &light[s - 1];
+        let lens = camera[0].p();
+        if qs.delta {
+            return;
+        }
+        let (film_s, film_t) = match scene.camera.project(lens, qs.p()) {
+            Some(st) => st,
+            None => return
+        };
+        let f = qs.f(lens, true);
+        if is_black(f) {
+            return;
+        }
+        let d = lens - qs.p();
+        let distance = d.length();
+        let wi = d / distance;
+        if !scene.unoccluded(qs.p(), wi, distance) {
+            return;
+        }
+        let importance = scene.camera.pdf_direction(lens, qs.p());
+        let value = qs.beta * f * (dot(wi, qs.rec.normal).abs() / (distance * distance) * importance);
+        let weight = self.mis_weight(scene, light, &camera[..1], s, 1);
+        splats.push(Splat {
+            s: film_s,
+            t: film_t,
+            value: weight * value
+        });
+    }
+
+    fn light_subpath(&self, scene: &Scene) -> Vec<Vertex> {
+        let mut path = vec![];
+        let (rec, pdf_pos) = match scene.lights.sample_surface() {
+            Some(sample) => sample,
+            None => return path
+        };
+        let frame = Onb::from_w(rec.normal);
+        let direction = frame.local(random_cosine_direction());
+        let cosine = dot(direction, rec.normal);
+        let pdf_dir = cosine / f32::consts::PI;
+        let origin = Vertex::endpoint(VertexKind::Light, rec, Vec3::one() / pdf_pos, pdf_pos);
+        let le = origin.le(origin.p() + direction);
+        path.push(origin);
+        if pdf_dir <= 0.0 || is_black(le) {
+            return path;
+        }
+        let beta = le * (cosine / (pdf_pos * pdf_dir));
+        let ray = Ray::new(path[0].p(), direction);
+        self.random_walk(scene, ray, beta, pdf_dir, &mut path);
+        path
+    }
+}
+
+impl Integrator for Bidirectional {
+    fn li(&self, r: &Ray, scene: &Scene, splats: &mut Vec<Splat>) -> Vec3 {
+        let mut lens = HitRecord::new();
+        lens.p = r.origin();
+        let first_hit = r.origin() + r.direction();
+        let mut camera = vec![Vertex::endpoint(VertexKind::Camera, lens, Vec3::one(), 1.0)];
+        let pdf = scene.camera.pdf_direction(r.origin(), first_hit);
+        let escaped = self.random_walk(scene, Ray::new(r.origin(), r.direction()), Vec3::one(), pdf, &mut camera);
+        let light = self.light_subpath(scene);
+
+        let mut radiance = Vec3::zero();
+        if let Some(ray) = escaped {
+            radiance += camera[camera.len() - 1].beta * scene.background.value(&ray);
+        }
+        for t in 2..=camera.len() {
+            let pt = &camera[t - 1];
+            if !pt.delta && !scene.punctual_lights.is_empty() {
+                if let Some(material) = pt.material() {
+                    let incoming = Ray::new(pt.p() + pt.wo, -pt.wo);
+                    radiance += pt.beta * sample_punctual_lights(&incoming, &pt.rec, material.as_ref(), scene, &Rgb);
+                }
+            }
+            for s in 0..=light.len() {
+                let contribution = self.connect(scene, &light, &camera, s, t);
+                if !is_black(contribution) {
+                    radiance += self.mis_weight(scene, &light, &camera, s, t) * contribution;
+                }
+            }
+        }
+        for s in 1..=light.len() {
+            self.splat(scene, &light, &camera, s, splats);
+        }
+        radiance
+    }
+
+    /// Neither subpath tracks the medium it is in, and connections can't see through one.
+    fn supports_media(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,90 @@
+use std::f32;
+use std::sync::Arc;
+
+use crate::math::ray::Ray;
+use crate::math::vec3::*;
+use crate::world::hitable::*;
+use crate::world::scene::Scene;
+use super::*;
+
+/// Path tracer that takes both a light sample and a BSDF sample at every non-specular hit
+/// and weights each with the power heuristic, so whichever strategy suits the lobe and
+/// light at hand dominates.
+pub struct MultipleImportance {
+    length: PathLength
+}
+
+impl MultipleImportance {
+    pub fn new(length: PathLength) -> Self {
+        MultipleImportance {
+            length
+        }
+    }
+}
+
+impl Default for MultipleImportance {
+    fn default() -> Self {
+        MultipleImportance::new(PathLength::default())
+    }
+}
+
+impl Integrator for MultipleImportance {
+    fn li(&self, r: &Ray, scene: &Scene, _splats: &mut Vec<Splat>) -> Vec3 {
+        let mut ray = Ray::new(r.origin(), r.direction());
+        let mut throughput = Vec3::one();
+        let mut radiance = Vec3::zero();
+        // Density of the bounce that produced `ray`, or `None` when light sampling could not
+        // have produced it (camera rays and specular bounces).
+        let mut bsdf_pdf: Option<f32> = None;
+        let mut medium = PathMedium::default();
+        let mut depth = 0;
+        loop {
+            let mut rec = HitRecord::new();
+            let hit = scene.world.hit(&ray, 0.001, f32::MAX, &mut rec);
+            if let Some(scattered) = medium.scatter(&ray, hit, rec.t, &mut throughput) {
+                // Nothing samples lights from inside a medium.
+                bsdf_pdf = None;
+                depth += 1;
+                if !self.length.continue_path(depth, &mut throughput) {
+                    break;
+                }
+                ray = scattered;
+                continue;
+            }
+            if !hit {
+                radiance += throughput * scene.background.value(&ray);
+                break;
+            }
+            let material = match rec.material {
+                Some(ref material) => Arc::clone(material),
+                None => break
+            };
+            let mut emitted = material.emitted(&ray, &rec);
+            if let Some(bsdf_pdf) = bsdf_pdf {
+                // Lights that aren't in the light list have zero light pdf and keep their full weight.
+                emitted = emitted * power_heuristic(bsdf_pdf, scene.lights.pdf_value(ray.origin(), ray.direction()));
+            }
+            radiance += throughput * emitted;
+            let srec = match material.sample(&ray, &rec) {
+                Some(srec) => srec,
+                None => break
+            };
+            if srec.is_specular {
+                bsdf_pdf = None;
+            }
+            else {
+                radiance += throughput * (sample_lights(&ray, &rec, material.as_ref(), scene, &Rgb)
+                    + sample_punctual_lights(&ray, &rec, material.as_ref(), scene, &Rgb));
+                bsdf_pdf = Some(srec.pdf);
+            }
+            medium.cross(material.as_ref(), &rec, srec.scattered.direction());
+            throughput = throughput * srec.attenuation;
+            depth += 1;
+            if !self.length.continue_path(depth, &mut throughput) {
+                break;
+            }
+            ray = srec.scattered;
+        }
+        radiance
+    }
+}
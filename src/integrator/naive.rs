@@ -0,0 +1,71 @@
+use std::f32;
+use std::sync::Arc;
+
+use crate::math::ray::Ray;
+use crate::math::vec3::*;
+use crate::world::hitable::*;
+use crate::world::scene::Scene;
+use super::*;
+
+/// Follows one sampled direction per bounce and only picks up light that the path hits by chance,
+/// so punctual lights contribute nothing.
+pub struct Naive {
+    length: PathLength
+}
+
+impl Naive {
+    pub fn new(length: PathLength) -> Self {
+        Naive {
+            length
+        }
+    }
+}
+
+impl Default for Naive {
+    fn default() -> Self {
+        Naive::new(PathLength::default())
+    }
+}
+
+impl Integrator for Naive {
+    fn li(&self, r: &Ray, scene: &Scene, _splats: &mut Vec<Splat>) -> Vec3 {
+        let mut ray = Ray::new(r.origin(), r.direction());
+        let mut throughput = Vec3::one();
+        let mut radiance = Vec3::zero();
+        let mut medium = PathMedium::default();
+        let mut depth = 0;
+        loop {
+            let mut rec = HitRecord::new();
+            let hit = scene.world.hit(&ray, 0.001, f32::MAX, &mut rec);
+            if let Some(scattered) = medium.scatter(&ray, hit, rec.t, &mut throughput) {
+                depth += 1;
+                if !self.length.continue_path(depth, &mut throughput) {
+                    break;
+                }
+                ray = scattered;
+                continue;
+            }
+            if !hit {
+                radiance += throughput * scene.background.value(&ray);
+                break;
+            }
+            let material = match rec.material {
+                Some(ref material) => Arc::clone(material),
+                None => break
+            };
+            radiance += throughput * material.emitted(&ray, &rec);
+            let srec = match material.sample(&ray, &rec) {
+                Some(srec) => srec,
+                None => break
+            };
+            medium.cross(material.as_ref(), &rec, srec.scattered.direction());
+            throughput = throughput * srec.attenuation;
+            depth += 1;
+            if !self.length.continue_path(depth, &mut throughput) {
+                break;
+            }
+            ray = srec.scattered;
+        }
+        radiance
+    }
+}
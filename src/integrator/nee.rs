@@ -0,0 +1,111 @@
+use std::f32;
+use std::sync::Arc;
+
+use crate::math::ray::Ray;
+use crate::math::vec3::*;
+use crate::world::hitable::*;
+use crate::world::materials::Material;
+use crate::world::scene::Scene;
+use super::*;
+
+/// Path tracer with next-event estimation: every non-specular hit sends a shadow ray
+/// towards a point on one of the scene's lights. Emitters found by a bounce are then
+/// ignored, except straight from the camera or after a specular bounce, since light
+/// sampling already accounted for them.
+pub struct NextEventEstimation {
+    length: PathLength
+}
+
+impl NextEventEstimation {
+    pub fn new(length: PathLength) -> Self {
+        NextEventEstimation {
+            length
+        }
+    }
+}
+
+impl Default for NextEventEstimation {
+    fn default() -> Self {
+        NextEventEstimation::new(PathLength::default())
+    }
+}
+
+/// Direct light from one sample of the light list, or zero when it is blocked.
+fn sample_lights(r: &Ray, rec: &HitRecord, material: &dyn Material, scene: &Scene) -> Vec3 {
+    if scene.lights.is_empty() {
+        return Vec3::zero();
+    }
+    let wi = unit_vector(scene.lights.random(rec.p));
+    let pdf = scene.lights.pdf_value(rec.p, wi);
+    if pdf <= 0.0 {
+        return Vec3::zero();
+    }
+    let wo = -unit_vector(r.direction());
+    let f = material.eval(wo, wi, rec);
+    if f.x() <= 0.0 && f.y() <= 0.0 && f.z() <= 0.0 {
+        return Vec3::zero();
+    }
+    // The closest hit along the shadow ray is whatever the light's radiance has to come from.
+    let shadow_ray = Ray::new(rec.p, wi);
+    let mut light_rec = HitRecord::new();
+    if !scene.world.hit(&shadow_ray, 0.001, f32::MAX, &mut light_rec) {
+        return Vec3::zero();
+    }
+    match light_rec.material {
+        Some(ref light) => f * light.emitted(&shadow_ray, &light_rec) * (dot(wi, rec.normal).abs() / pdf),
+        None => Vec3::zero()
+    }
+}
+
+impl Integrator for NextEventEstimation {
+    fn li(&self, r: &Ray, scene: &Scene, _splats: &mut Vec<Splat>) -> Vec3 {
+        let mut ray = Ray::new(r.origin(), r.direction());
+        let mut throughput = Vec3::one();
+        let mut radiance = Vec3::zero();
+        let mut count_emitted = true;
+        let mut medium = PathMedium::default();
+        let mut depth = 0;
+        loop {
+            let mut rec = HitRecord::new();
+            let hit = scene.world.hit(&ray, 0.001, f32::MAX, &mut rec);
+            if let Some(scattered) = medium.scatter(&ray, hit, rec.t, &mut throughput) {
+                // Nothing samples lights from inside a medium.
+                count_emitted = true;
+                depth += 1;
+                if !self.length.continue_path(depth, &mut throughput) {
+                    break;
+                }
+                ray = scattered;
+                continue;
+            }
+            if !hit {
+                radiance += throughput * scene.background.value(&ray);
+                break;
+            }
+            let material = match rec.material {
+                Some(ref material) => Arc::clone(material),
+                None => break
+            };
+            if count_emitted {
+                radiance += throughput * material.emitted(&ray, &rec);
+            }
+            let srec = match material.sample(&ray, &rec) {
+                Some(srec) => srec,
+                None => break
+            };
+            if !srec.is_specular {
+                radiance += throughput * (sample_lights(&ray, &rec, material.as_ref(), scene)
+                    + sample_punctual_lights(&ray, &rec, material.as_ref(), scene, &Rgb));
+            }
+            count_emitted = srec.is_specular;
+            medium.cross(material.as_ref(), &rec, srec.scattered.direction());
+            throughput = throughput * srec.attenuation;
+            depth += 1;
+            if !self.length.continue_path(depth, &mut throughput) {
+                break;
+            }
+            ray = srec.scattered;
+        }
+        radiance
+    }
+}
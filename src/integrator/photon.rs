@@ -0,0 +1,257 @@
+extern crate rayon;
+use rayon::prelude::*;
+
+use std::f32;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+use crate::math::onb::Onb;
+use crate::math::ray::Ray;
+use crate::math::vec3::*;
+use crate::world::hitable::*;
+use crate::world::materials::Material;
+use crate::world::scene::Scene;
+use super::*;
+
+/// Shrink rate of the progressive gather radius (Knaus and Zwicker 2011); lower shrinks faster.
+const ALPHA: f32 = 2.0 / 3.0;
+
+#[derive(Debug, Copy, Clone)]
+struct Photon {
+    position: Vec3,
+    /// Unit direction the photon arrived from.
+    wi: Vec3,
+    power: Vec3,
+}
+
+/// Photons in a balanced kd-tree, stored implicitly: the median of every range is its node.
+struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<usize>,
+}
+
+impl PhotonMap {
+    fn new(mut photons: Vec<Photon>) -> Self {
+        let mut axes = vec![0; photons.len()];
+        build(&mut photons, &mut axes);
+        PhotonMap {
+            photons,
+            axes
+        }
+    }
+
+    /// Calls `found` for every photon within `radius` of `p`.
+    fn gather<F: FnMut(&Photon)>(&self, p: Vec3, radius: f32, found: &mut F) {
+        self.query(0, self.photons.len(), p, radius * radius, found);
+    }
+
+    fn query<F: FnMut(&Photon)>(&self, lo: usize, hi: usize, p: Vec3, radius_squared: f32, found: &mut F) {
+        if lo >= hi {
+            return;
+        }
+        let mid = lo + (hi - lo) / 2;
+        let photon = &self.photons[mid];
+        if (photon.position - p).squared_length() <= radius_squared {
+            found(photon);
+        }
+        let axis = self.axes[mid];
+        let d = p[axis] - photon.position[axis];
+        let (near, far) = if d <= 0.0 { ((lo, mid), (mid + 1, hi)) } else { ((mid + 1, hi), (lo, mid)) };
+        self.query(near.0, near.1, p, radius_squared, found);
+        if d * d <= radius_squared {
+            self.query(far.0, far.1, p, radius_squared, found);
+        }
+    }
+}
+
+fn build(photons: &mut [Photon], axes: &mut [usize]) {
+    if photons.is_empty() {
+        return;
+    }
+    let mut min = photons[0].position;
+    let mut max = photons[0].position;
+    for photon in photons.iter() {
+        let q = photon.position;
+        min = Vec3::new(min.x().min(q.x()), min.y().min(q.y()), min.z().min(q.z()));
+        max = Vec3::new(max.x().max(q.x()), max.y().max(q.y()), max.z().max(q.z()));
+    }
+    let extent = max - min;
+    let axis = if extent.x() > extent.y() && extent.x() > extent.z() { 0 } else if extent.y() > extent.z() { 1 } else { 2 };
+    let mid = photons.len() / 2;
+    photons.select_nth_unstable_by(mid, |a, b| a.position[axis].partial_cmp(&b.position[axis]).unwrap_or(std::cmp::Ordering::Equal));
+    axes[mid] = axis;
+    let (left, right) = photons.split_at_mut(mid);
+    let (left_axes, right_axes) = axes.split_at_mut(mid);
+    build(left, left_axes);
+    build(&mut right[1..], &mut right_axes[1..]);
+}
+
+struct Pass {
+    map: PhotonMap,
+    radius: f32,
+}
+
+/// Path tracer (as `MultipleImportance`) that leaves caustics, light reaching a non-specular
+/// surface through one or more specular bounces, to a caustic photon map. Photons are shot from
+/// the lights on the light list, stored where they first land on a non-specular surface after a
+/// specular bounce, and gathered within a fixed radius at every non-specular camera vertex.
+///
+/// A fixed radius blurs caustics and never converges. In progressive mode every pass shoots a
+/// fresh map and gathers with a smaller radius, so the average over passes is consistent.
+/// Punctual lights shoot no photons and so cast no caustics.
+pub struct PhotonMapping {
+    length: PathLength,
+    photons_per_pass: usize,
+    initial_radius: f32,
+    progressive: bool,
+    pass: RwLock<Pass>
+}
+
+impl PhotonMapping {
+    pub fn new(length: PathLength, photons_per_pass: usize, radius: f32, progressive: bool) -> Self {
+        PhotonMapping {
+            length,
+            photons_per_pass,
+            initial_radius: radius,
+            progressive,
+            pass: RwLock::new(Pass {
+                map: PhotonMap::new(vec![]),
+                radius
+            })
+        }
+    }
+
+    /// Follows one photon until it leaves the caustic paths; only photons that reach a
+    /// non-specular surface through specular bounces are kept.
+    fn trace_photon(&self, scene: &Scene) -> Option<Photon> {
+        let (rec, pdf_pos) = scene.lights.sample_surface()?;
+        let light = rec.material.clone()?;
+        let frame = Onb::from_w(rec.normal);
+        let direction = frame.local(random_cosine_direction());
+        let cosine = dot(direction, rec.normal);
+        if cosine <= 0.0 {
+            return None;
+        }
+        let le = light.emitted(&Ray::new(rec.p + direction, -direction), &rec);
+        // Le cos / (pdf_pos pdf_dir) with pdf_dir = cos / pi, shared between all photons of the pass.
+        let mut power = le * (f32::consts::PI / (pdf_pos * self.photons_per_pass as f32));
+        let mut ray = Ray::new(rec.p, direction);
+        let mut depth = 0;
+        loop {
+            let mut rec = HitRecord::new();
+            if !scene.world.hit(&ray, 0.001, f32::MAX, &mut rec) {
+                return None;
+            }
+            let material = rec.material.clone()?;
+            let srec = material.sample(&ray, &rec)?;
+            if !srec.is_specular {
+                if depth == 0 {
+                    return None;
+                }
+                return Some(Photon {
+                    position: rec.p,
+                    wi: -unit_vector(ray.direction()),
+                    power
+                });
+            }
+            power = power * srec.attenuation;
+            depth += 1;
+            if !self.length.continue_path(depth, &mut power) {
+                return None;
+            }
+            ray = srec.scattered;
+        }
+    }
+
+    fn caustics(&self, pass: &Pass, wo: Vec3, rec: &HitRecord, material: &dyn Material) -> Vec3 {
+        let mut sum = Vec3::zero();
+        pass.map.gather(rec.p, pass.radius, &mut |photon| {
+            sum += material.eval(wo, photon.wi, rec) * photon.power;
+        });
+        sum / (f32::consts::PI * pass.radius * pass.radius)
+    }
+}
+
+impl Integrator for PhotonMapping {
+    fn li(&self, r: &Ray, scene: &Scene, _splats: &mut Vec<Splat>) -> Vec3 {
+        let pass = self.pass.read().unwrap();
+        let mut ray = Ray::new(r.origin(), r.direction());
+        let mut throughput = Vec3::one();
+        let mut radiance = Vec3::zero();
+        let mut bsdf_pdf: Option<f32> = None;
+        // Set once the path has scattered off a non-specular surface.
+        let mut diffuse_seen = false;
+        let mut depth = 0;
+        loop {
+            let mut rec = HitRecord::new();
+            if !scene.world.hit(&ray, 0.001, f32::MAX, &mut rec) {
+                radiance += throughput * scene.background.value(&ray);
+                break;
+            }
+            let material = match rec.material {
+                Some(ref material) => Arc::clone(material),
+                None => break
+            };
+            let mut emitted = material.emitted(&ray, &rec);
+            match bsdf_pdf {
+                Some(bsdf_pdf) => {
+                    emitted = emitted * power_heuristic(bsdf_pdf, scene.lights.pdf_value(ray.origin(), ray.direction()));
+                },
+                None => {
+                    // A specular chain back to a non-specular surface: the photons carry it.
+                    if diffuse_seen && material.is_emissive() && scene.lights.surface_pdf(&ray, rec.t) > 0.0 {
+                        emitted = Vec3::zero();
+                    }
+                }
+            }
+            radiance += throughput * emitted;
+            let srec = match material.sample(&ray, &rec) {
+                Some(srec) => srec,
+                None => break
+            };
+            if srec.is_specular {
+                bsdf_pdf = None;
+            }
+            else {
+                let wo = -unit_vector(ray.direction());
+                radiance += throughput * (sample_lights(&ray, &rec, material.as_ref(), scene, &Rgb)
+                    + sample_punctual_lights(&ray, &rec, material.as_ref(), scene, &Rgb)
+                    + self.caustics(&pass, wo, &rec, material.as_ref()));
+                bsdf_pdf = Some(srec.pdf);
+                diffuse_seen = true;
+            }
+            throughput = throughput * srec.attenuation;
+            depth += 1;
+            if !self.length.continue_path(depth, &mut throughput) {
+                break;
+            }
+            ray = srec.scattered;
+        }
+        radiance
+    }
+
+    fn begin_pass(&self, scene: &Scene, pass: usize) {
+        if pass > 0 && !self.progressive {
+            return;
+        }
+        let photons: Vec<Photon> = (0..self.photons_per_pass).into_par_iter()
+            .filter_map(|_| self.trace_photon(scene))
+            .collect();
+        let mut radius_squared = self.initial_radius * self.initial_radius;
+        for i in 1..=pass {
+            radius_squared *= (i as f32 + ALPHA) / (i as f32 + 1.0);
+        }
+        let mut state = self.pass.write().unwrap();
+        state.map = PhotonMap::new(photons);
+        state.radius = radius_squared.sqrt();
+    }
+
+    fn progressive(&self) -> bool {
+        self.progressive
+    }
+
+    /// Photons are neither traced through media nor stored inside them.
+    fn supports_media(&self) -> bool {
+        false
+    }
+}
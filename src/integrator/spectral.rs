@@ -0,0 +1,109 @@
+extern crate rand;
+use rand::Rng;
+
+use std::f32;
+use std::sync::Arc;
+
+use crate::math::ray::Ray;
+use crate::math::spectrum::*;
+use crate::math::vec3::*;
+use crate::world::hitable::*;
+use crate::world::scene::Scene;
+use super::*;
+
+/// Path tracer that follows a few wavelengths per path instead of RGB, so dispersive dielectrics
+/// split light into its colors. Otherwise it works like `MultipleImportance`. Albedos, lights
+/// and the background are still RGB and get uplifted to spectra where a path meets them. Samples
+/// reach the film as XYZ, and only the averaged pixels go on to linear sRGB.
+pub struct Spectral {
+    length: PathLength
+}
+
+impl Spectral {
+    pub fn new(length: PathLength) -> Self {
+        Spectral {
+            length
+        }
+    }
+}
+
+impl Default for Spectral {
+    fn default() -> Self {
+        Spectral::new(PathLength::default())
+    }
+}
+
+impl Radiance for SampledWavelengths {
+    type Value = SampledSpectrum;
+
+    fn lift(&self, rgb: Vec3) -> SampledSpectrum {
+        uplift(rgb, self)
+    }
+
+    fn trace(&self, ray: Ray) -> Ray {
+        ray.with_wavelength(self.hero())
+    }
+}
+
+impl Integrator for Spectral {
+    fn li(&self, r: &Ray, scene: &Scene, _splats: &mut Vec<Splat>) -> Vec3 {
+        let mut wavelengths = SampledWavelengths::sample_uniform(rand::thread_rng().gen());
+        let mut ray = Ray::new(r.origin(), r.direction()).with_wavelength(wavelengths.hero());
+        let mut throughput = SampledSpectrum::one();
+        let mut radiance = SampledSpectrum::zero();
+        let mut bsdf_pdf: Option<f32> = None;
+        let mut depth = 0;
+        loop {
+            let mut rec = HitRecord::new();
+            if !scene.world.hit(&ray, 0.001, f32::MAX, &mut rec) {
+                radiance += throughput * uplift(scene.background.value(&ray), &wavelengths);
+                break;
+            }
+            let material = match rec.material {
+                Some(ref material) => Arc::clone(material),
+                None => break
+            };
+            let mut emitted = uplift(material.emitted(&ray, &rec), &wavelengths);
+            if let Some(bsdf_pdf) = bsdf_pdf {
+                emitted = emitted * power_heuristic(bsdf_pdf, scene.lights.pdf_value(ray.origin(), ray.direction()));
+            }
+            radiance += throughput * emitted;
+            let srec = match material.sample(&ray, &rec) {
+                Some(srec) => srec,
+                None => break
+            };
+            if material.is_dispersive() {
+                // The direction taken only suits the hero wavelength.
+                wavelengths.terminate_secondary();
+            }
+            if srec.is_specular {
+                bsdf_pdf = None;
+            }
+            else {
+                radiance += throughput * (sample_lights(&ray, &rec, material.as_ref(), scene, &wavelengths)
+                    + sample_punctual_lights(&ray, &rec, material.as_ref(), scene, &wavelengths));
+                bsdf_pdf = Some(srec.pdf);
+            }
+            throughput = throughput * uplift(srec.attenuation, &wavelengths);
+            depth += 1;
+            match self.length.survival(depth, throughput.max_value()) {
+                Some(survival) => throughput = throughput / survival,
+                None => break
+            }
+            ray = srec.scattered.with_wavelength(wavelengths.hero());
+        }
+        wavelengths.to_xyz(radiance)
+    }
+
+    /// The film adds up XYZ, which only becomes RGB once the samples are averaged.
+    fn to_rgb(&self, xyz: Vec3) -> Vec3 {
+        // Saturated spectral colors can fall outside sRGB.
+        let rgb = xyz_to_rgb(xyz);
+        Vec3::new(rgb.x().max(0.0), rgb.y().max(0.0), rgb.z().max(0.0))
+    }
+
+    /// Random walks in media only track RGB throughput.
+    fn supports_media(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,3 @@
+pub mod integrator;
+pub mod math;
+pub mod world;
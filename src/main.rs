@@ -4,56 +4,41 @@ use rand::Rng;
 extern crate rayon;
 use rayon::prelude::*;
 
+use std::env;
 use std::path::Path;
 use std::fs::File;
 use std::io::Write;
-use std::f32;
 use std::sync::Arc;
 
-mod math;
-use math::vec3::Vec3;
-use math::ray::Ray;
-
-mod world;
-use world::bvh::*;
-use world::hitable::*;
-use world::camera::*;
-use world::materials::*;
-
-fn color(r: Ray, world: &dyn Hitable, depth: i32) -> Vec3 {
-    let mut rec = HitRecord::new();
-    if world.hit(&r, 0.001, f32::MAX, &mut rec) {
-        if depth < 50 {
-            match rec.material {
-                None => {
-                    Vec3::zero()
-                },
-                Some (ref material) => {
-                    let (scatter_result, attenuation, scattered) = material.scatter(&r, &rec);
-                    if scatter_result {
-                        attenuation * color(scattered, world, depth + 1)
-                    }
-                    else {
-                        Vec3::zero()
-                    }
-                }
-            }
-        }
-        else {
-            Vec3::zero()
-        }
-    }
-    else {
-        let unit_direction = math::vec3::unit_vector(r.direction());
-        let t = 0.5 * (unit_direction.y() + 1.0);
-        return (1.0 - t) * Vec3::new(1.0, 1.0, 1.0) + t * Vec3::new(0.5, 0.7, 1.0)
-    }
-}
+use raytracer::integrator::*;
+use raytracer::integrator::ao::AmbientOcclusion;
+use raytracer::integrator::aov::{Aov, AovKind};
+use raytracer::integrator::bdpt::Bidirectional;
+use raytracer::integrator::mis::MultipleImportance;
+use raytracer::integrator::naive::Naive;
+use raytracer::integrator::nee::NextEventEstimation;
+use raytracer::integrator::photon::PhotonMapping;
+use raytracer::integrator::spectral::Spectral;
+use raytracer::math::vec3::Vec3;
+
+use raytracer::world::hitable::*;
+use raytracer::world::lights::PunctualLight;
+use raytracer::world::camera::*;
+use raytracer::world::materials::*;
+use raytracer::world::mesh::*;
+use raytracer::world::microfacet::RoughConductor;
+use raytracer::world::normal_map::NormalMapped;
+use raytracer::world::quad::Quad;
+use raytracer::world::scene::*;
+use raytracer::world::scene_file::load_scene;
+use raytracer::world::subdivision::loop_subdivide;
+use raytracer::world::texture::*;
 
 fn random_scene() -> Vec<Arc<dyn Hitable+Send+Sync>> {
     let mut rng = rand::thread_rng();
     let mut hitable: Vec<Arc<dyn Hitable+Send+Sync>> = vec![];
-    hitable.push(Arc::new(Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))))));
+    let ground = Arc::new(Marble::new(Vec3::new(0.5, 0.5, 0.5), Vec3::new(0.25, 0.25, 0.27), 2.0));
+    hitable.push(Arc::new(Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, Arc::new(Lambertian::with_texture(ground)))));
     for a in -11..11 {
         for b in -11..11 {
             let center = Vec3::new(a as f32 + 0.9 * rng.gen::<f32>(), 0.2, b as f32 + 0.9 * rng.gen::<f32>());
@@ -63,7 +48,7 @@ fn random_scene() -> Vec<Arc<dyn Hitable+Send+Sync>> {
                     hitable.push(Arc::new(Sphere::new(center, 0.2, Arc::new(Lambertian::new(Vec3::new(rng.gen::<f32>() * rng.gen::<f32>(), rng.gen::<f32>() * rng.gen::<f32>(), rng.gen::<f32>()* rng.gen::<f32>()))))));
                 }
                 else if rand < 0.95 {
-                    hitable.push(Arc::new(Sphere::new(center, 0.2, Arc::new(Metal::new(Vec3::new(0.5 * (1.0 + rng.gen::<f32>()), 0.5 * (1.0 + rng.gen::<f32>()), 0.5 * (1.0 + rng.gen::<f32>())))))));
+                    hitable.push(Arc::new(Sphere::new(center, 0.2, Arc::new(Metal::new(Vec3::new(0.5 * (1.0 + rng.gen::<f32>()), 0.5 * (1.0 + rng.gen::<f32>()), 0.5 * (1.0 + rng.gen::<f32>())), 0.5 * rng.gen::<f32>())))));
                 }
                 else {
                     hitable.push(Arc::new(Sphere::new(center, 0.2, Arc::new(Dielectric::new(1.5)))));
@@ -71,12 +56,78 @@ fn random_scene() -> Vec<Arc<dyn Hitable+Send+Sync>> {
             }
         }
     }
-    hitable.push(Arc::new(Sphere::new(Vec3::new(0.0, 1.0, 0.0), 1.0, Arc::new(Metal::new(Vec3::new(0.3, 0.9, 0.4))))));
+    hitable.push(Arc::new(Sphere::new(Vec3::new(0.0, 1.0, 0.0), 1.0, Arc::new(Metal::new(Vec3::new(0.3, 0.9, 0.4), 0.0)))));
     hitable.push(Arc::new(Sphere::new(Vec3::new(-4.0, 1.0, 0.0), 1.0, Arc::new(Lambertian::new(Vec3::new(0.4, 0.2, 0.1))))));
-    hitable.push(Arc::new(Sphere::new(Vec3::new(4.0, 1.0, 0.0), 1.0, Arc::new(Metal::new(Vec3::new(0.7, 0.6, 0.5))))));
+    hitable.push(Arc::new(Sphere::new(Vec3::new(4.0, 1.0, 0.0), 1.0, Arc::new(Metal::new(Vec3::new(0.7, 0.6, 0.5), 0.0)))));
     hitable
 }
 
+/// Closed box with a small ceiling light, a ball of `glass` and a glossy metal ball; hard for
+/// unidirectional tracing because the light is tiny.
+fn cornell_box(glass: Dielectric) -> Vec<Arc<dyn Hitable+Send+Sync>> {
+    let red: Arc<dyn Material> = Arc::new(Lambertian::new(Vec3::new(0.65, 0.05, 0.05)));
+    let white: Arc<dyn Material> = Arc::new(Lambertian::new(Vec3::new(0.73, 0.73, 0.73)));
+    let green: Arc<dyn Material> = Arc::new(Lambertian::new(Vec3::new(0.12, 0.45, 0.15)));
+    let light: Arc<dyn Material> = Arc::new(DiffuseLight::new(Vec3::new(15.0, 15.0, 15.0)));
+    vec![
+        Arc::new(Quad::new(Vec3::new(555.0, 0.0, 0.0), Vec3::new(0.0, 555.0, 0.0), Vec3::new(0.0, 0.0, 555.0), green)),
+        Arc::new(Quad::new(Vec3::new(0.0, 0.0, 555.0), Vec3::new(0.0, 555.0, 0.0), Vec3::new(0.0, 0.0, -555.0), red)),
+        Arc::new(Quad::new(Vec3::new(343.0, 554.0, 332.0), Vec3::new(-130.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -105.0), light)),
+        Arc::new(Quad::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 555.0), Vec3::new(555.0, 0.0, 0.0), Arc::clone(&white))),
+        Arc::new(Quad::new(Vec3::new(555.0, 555.0, 555.0), Vec3::new(-555.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -555.0), Arc::clone(&white))),
+        Arc::new(Quad::new(Vec3::new(0.0, 0.0, 555.0), Vec3::new(555.0, 0.0, 0.0), Vec3::new(0.0, 555.0, 0.0), white)),
+        Arc::new(Sphere::new(Vec3::new(190.0, 90.0, 190.0), 90.0, Arc::new(glass))),
+        Arc::new(Sphere::new(Vec3::new(380.0, 100.0, 380.0), 100.0, Arc::new(RoughConductor::aluminium(0.3)))),
+    ]
+}
+
+/// Marble bumps on a clay ball and on a gold one, next to a patch of ground actually
+/// displaced by the same marble, so the silhouettes show the difference.
+fn bumpy_scene() -> Vec<Arc<dyn Hitable+Send+Sync>> {
+    let marble: Arc<dyn Texture> = Arc::new(Marble::new(Vec3::one(), Vec3::zero(), 4.0));
+    let clay: Arc<dyn Material> = Arc::new(Lambertian::new(Vec3::new(0.7, 0.45, 0.35)));
+    let gold: Arc<dyn Material> = Arc::new(RoughConductor::gold(0.15));
+    let ground: Arc<dyn Material> = Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)));
+    let mut patch = Mesh::new();
+    patch.positions = vec![Vec3::new(-1.0, 0.0, 1.0), Vec3::new(1.0, 0.0, 1.0), Vec3::new(1.0, 0.0, -1.0), Vec3::new(-1.0, 0.0, -1.0)];
+    patch.uvs = vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+    patch.faces.push(Face {
+        vertices: vec![0, 1, 2, 3],
+        uvs: vec![0, 1, 2, 3],
+        normals: vec![]
+    });
+    let mut patch = loop_subdivide(&patch, 6);
+    patch.displace_texture(marble.as_ref(), 0.08);
+    let mut hitables: Vec<Arc<dyn Hitable+Send+Sync>> = vec![
+        Arc::new(Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, Arc::clone(&ground))),
+        Arc::new(Sphere::new(Vec3::new(-1.2, 0.7, -0.5), 0.7, Arc::new(NormalMapped::with_bumps(clay, Arc::clone(&marble), 0.01)))),
+        Arc::new(Sphere::new(Vec3::new(1.2, 0.7, -0.5), 0.7, Arc::new(NormalMapped::with_bumps(gold, marble, 0.01)))),
+    ];
+    for triangle in patch.triangles(ground) {
+        hitables.push(triangle);
+    }
+    hitables
+}
+
+/// Sunlit fence cut out of a single quad with a checker opacity, casting a checkered shadow
+/// over the balls behind it.
+fn fence_scene() -> Vec<Arc<dyn Hitable+Send+Sync>> {
+    let holes: Arc<dyn Texture> = Arc::new(Checker::from_colors(Vec3::zero(), Vec3::one(), 0.2));
+    let wood: Arc<dyn Material> = Arc::new(Lambertian::new(Vec3::new(0.55, 0.35, 0.2)));
+    vec![
+        Arc::new(Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))))),
+        Arc::new(Quad::new(Vec3::new(-2.0, 0.0, 0.1), Vec3::new(4.0, 0.0, 0.0), Vec3::new(0.0, 1.4, 0.0), Arc::new(Cutout::new(wood, holes, 0.5)))),
+        Arc::new(Sphere::new(Vec3::new(-0.8, 0.5, -1.2), 0.5, Arc::new(Lambertian::new(Vec3::new(0.7, 0.45, 0.35))))),
+        Arc::new(Sphere::new(Vec3::new(0.9, 0.5, -1.5), 0.5, Arc::new(RoughConductor::gold(0.2)))),
+    ]
+}
+
+/// Value following `flag` on the command line, e.g. `--integrator nee`.
+fn argument(flag: &str) -> Option<String> {
+    let args: Vec<String> = env::args().collect();
+    args.iter().position(|a| a == flag).and_then(|i| args.get(i + 1).cloned())
+}
+
 fn main() {
     let file_name = "output.ppm";
     let file_path = Path::new(file_name);
@@ -89,46 +140,165 @@ fn main() {
     let height = 400;
 
     write!(file, "P3\n{} {}\n255\n", width,height).expect("Could not write to file");
-    let mut hitable: Vec<Arc<Hitable+Send+Sync>> = random_scene();
-        /*vec![
-            Box::new(Sphere::new(Vec3::new(0.0, 0.0, -1.0), 0.5, Rc::new(Lambertian::new(Vec3::new(0.8, 0.3, 0.3))))),
-            Box::new(Sphere::new(Vec3::new(0.0, -100.5, -1.0), 100.0, Rc::new(Lambertian::new(Vec3::new(0.8, 0.8, 0.0))))),
-            Box::new(Sphere::new(Vec3::new(1.0, 0.0, -1.0), 0.5, Rc::new(Metal::new(Vec3::new(0.8, 0.6, 0.2))))),
-            Box::new(Sphere::new(Vec3::new(-1.0, 0.0, -1.0), 0.5, Rc::new(Dielectric::new(1.5)))),
-            Box::new(Sphere::new(Vec3::new(-1.0, 0.0, -1.0), -0.45, Rc::new(Dielectric::new(1.5)))),
-        ];*/
-
-    let world = BvhNode::new(&mut hitable);
-    let look_from = Vec3::new(13.0, 2.0, 3.0);
-    let look_at = Vec3::new(0.0, 0.0, 0.0);
-    let camera = Camera::new(look_from, look_at, Vec3::new(0.0, 1.0, 0.0), 20.0, (width as f32) / (height as f32), 0.1, 10.0);
-
-    let num_samples = 100;
+    let number = |flag: &str| argument(flag).map(|s| s.parse::<u32>().unwrap_or_else(|_| panic!("{} takes a number", flag)));
+    let length = PathLength::new(number("--min-depth").unwrap_or(3), number("--max-depth"));
+    let scene_name = argument("--scene").unwrap_or_else(|| "random".to_string());
+
+    let aspect = (width as f32) / (height as f32);
+    let scene = match argument("--scene-file") {
+        // A scene file takes precedence over the built in scenes.
+        Some(path) => load_scene(&path, aspect).unwrap_or_else(|e| panic!("Could not load scene {}: {}", path, e)),
+        None => match scene_name.as_str() {
+            "cornell" | "dispersion" => {
+                let look_from = Vec3::new(278.0, 278.0, -800.0);
+                let look_at = Vec3::new(278.0, 278.0, 0.0);
+                let camera = Camera::new(look_from, look_at, Vec3::new(0.0, 1.0, 0.0), 40.0, aspect, 0.0, 10.0);
+                // Dense flint splits the caustic into colors with `--integrator spectral`.
+                let glass = if scene_name == "dispersion" { Dielectric::with_dispersion(Dispersion::dense_flint()) } else { Dielectric::new(1.5) };
+                Scene::new(cornell_box(glass), camera, Background::Solid(Vec3::zero()))
+            },
+            "bumps" => {
+                let look_from = Vec3::new(0.0, 2.0, 4.5);
+                let look_at = Vec3::new(0.0, 0.4, 0.0);
+                let camera = Camera::new(look_from, look_at, Vec3::new(0.0, 1.0, 0.0), 35.0, aspect, 0.0, 10.0);
+                Scene::new(bumpy_scene(), camera, Background::Sky)
+            },
+            "fence" => {
+                let look_from = Vec3::new(0.5, 1.6, 4.5);
+                let look_at = Vec3::new(0.0, 0.6, -0.5);
+                let camera = Camera::new(look_from, look_at, Vec3::new(0.0, 1.0, 0.0), 35.0, aspect, 0.0, 10.0);
+                Scene::new(fence_scene(), camera, Background::Sky)
+                    .with_light(PunctualLight::directional(Vec3::new(-0.4, -1.0, -1.2), Vec3::new(3.0, 2.8, 2.5)))
+            },
+            "random" | "night" => {
+                let look_from = Vec3::new(13.0, 2.0, 3.0);
+                let look_at = Vec3::new(0.0, 0.0, 0.0);
+                let camera = Camera::new(look_from, look_at, Vec3::new(0.0, 1.0, 0.0), 20.0, aspect, 0.1, 10.0);
+                if scene_name == "night" {
+                    // Dim moonlight plus a warm street lamp and a spot on the green metal ball in the middle.
+                    Scene::new(random_scene(), camera, Background::Solid(Vec3::new(0.01, 0.01, 0.02)))
+                        .with_light(PunctualLight::directional(Vec3::new(-1.0, -2.0, -0.5), Vec3::new(0.15, 0.17, 0.25)))
+                        .with_light(PunctualLight::point(Vec3::new(-2.0, 3.0, 2.0), Vec3::new(12.0, 9.0, 5.0)))
+                        .with_light(PunctualLight::spot(Vec3::new(0.0, 6.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(40.0, 40.0, 40.0), 20.0, 12.0))
+                }
+                else {
+                    Scene::new(random_scene(), camera, Background::Sky)
+                }
+            },
+            other => panic!("Unknown scene: {}", other),
+        }
+    };
+
+    let integrator: Box<dyn Integrator> = match argument("--integrator").as_deref() {
+        None | Some("naive") => Box::new(Naive::new(length)),
+        Some("nee") => Box::new(NextEventEstimation::new(length)),
+        Some("mis") => Box::new(MultipleImportance::new(length)),
+        Some("bdpt") => Box::new(Bidirectional::new(length)),
+        Some("spectral") => Box::new(Spectral::new(length)),
+        Some(name @ "photon") | Some(name @ "ppm") => {
+            let photons = number("--photons").unwrap_or(200_000) as usize;
+            // Default to a gather radius of a small fraction of what the camera sees where the
+            // middle of the image lands. The scene's size is a poor guide once it holds a huge
+            // ground sphere, so that is only the fallback when the middle ray escapes.
+            let radius = argument("--photon-radius")
+                .map(|s| s.parse::<f32>().expect("--photon-radius takes a number"))
+                .unwrap_or_else(|| {
+                    let mut rec = HitRecord::new();
+                    if scene.world.hit(&scene.camera.get_ray(0.5, 0.5), 0.001, f32::MAX, &mut rec) {
+                        0.004 * scene.camera.view_height_at(rec.p)
+                    }
+                    else {
+                        let bounds = scene.world.bounding_box().aabb;
+                        0.004 * (bounds.max() - bounds.min()).length()
+                    }
+                });
+            Box::new(PhotonMapping::new(length, photons, radius, name == "ppm"))
+        },
+        Some("ao") => {
+            let bounds = scene.world.bounding_box().aabb;
+            let distance = argument("--ao-distance")
+                .map(|s| s.parse::<f32>().expect("--ao-distance takes a number"))
+                .unwrap_or_else(|| 0.1 * (bounds.max() - bounds.min()).length());
+            Box::new(AmbientOcclusion::new(number("--ao-samples").unwrap_or(16), distance))
+        },
+        Some("normals") => Box::new(Aov::new(AovKind::ShadingNormal)),
+        Some("geometric-normals") => Box::new(Aov::new(AovKind::GeometricNormal)),
+        Some("depth") => {
+            // Default to white at the corner of the scene farthest from the camera.
+            let bounds = scene.world.bounding_box().aabb;
+            let eye = scene.camera.get_ray(0.5, 0.5).origin();
+            let far = (eye - bounds.min()).length().max((eye - bounds.max()).length());
+            let range = argument("--depth-range")
+                .map(|s| s.parse::<f32>().expect("--depth-range takes a number"))
+                .unwrap_or(far);
+            Box::new(Aov::new(AovKind::Depth(range)))
+        },
+        Some("uv") => Box::new(Aov::new(AovKind::Uv)),
+        Some("material-id") => Box::new(Aov::new(AovKind::MaterialId)),
+        #[cfg(feature = "bvh-stats")]
+        Some("bvh-cost") => Box::new(Aov::new(AovKind::BvhCost(number("--bvh-cost-range").unwrap_or(64)))),
+        #[cfg(not(feature = "bvh-stats"))]
+        Some("bvh-cost") => panic!("bvh-cost needs the bvh-stats feature: cargo run --features bvh-stats"),
+        Some(other) => panic!("Unknown integrator: {}", other),
+    };
+    if scene.has_media && !integrator.supports_media() {
+        panic!("This scene has participating media, which only the naive, nee and mis integrators render");
+    }
+
+    let num_samples = argument("--samples").map(|s| s.parse::<usize>().expect("--samples takes a number")).unwrap_or(100);
 
     let mut color_buf = vec![vec![Vec3::zero(); width]; height];
-    
-    color_buf.par_iter_mut().enumerate().for_each(|(i, pixel)| {
-        let mut rng = rand::thread_rng();
-        for j in 0 .. width {
-            let mut col = Vec3::zero();
-            for _s in 0..num_samples {
-                let u = (j as f32 + rng.gen::<f32>()) / (width as f32);
-                let v = (i as f32 + rng.gen::<f32>()) / (height as f32);
-                let ray = camera.get_ray(u,v);
-                col += color(ray, &world, 0);
+
+    // Progressive integrators refine shared state between samples, so they get one sweep
+    // over the film per sample instead of all samples in one sweep.
+    let (passes, samples_per_pass) = if integrator.progressive() { (num_samples, 1) } else { (1, num_samples) };
+    let mut splat_buf = vec![Vec3::zero(); width * height];
+    for pass in 0..passes {
+        integrator.begin_pass(&scene, pass);
+        // Splats can land on any pixel, so each worker collects them on a full size film of its own.
+        let film = color_buf.par_iter_mut().enumerate().fold(|| vec![Vec3::zero(); width * height], |mut film, (i, pixel)| {
+            let mut rng = rand::thread_rng();
+            let mut splats = vec![];
+            for j in 0 .. width {
+                let mut col = Vec3::zero();
+                for _s in 0..samples_per_pass {
+                    let u = (j as f32 + rng.gen::<f32>()) / (width as f32);
+                    let v = (i as f32 + rng.gen::<f32>()) / (height as f32);
+                    let ray = scene.camera.get_ray(u,v);
+                    col += integrator.li(&ray, &scene, &mut splats);
+                    for splat in splats.drain(..) {
+                        let x = ((splat.s * width as f32) as usize).min(width - 1);
+                        let y = ((splat.t * height as f32) as usize).min(height - 1);
+                        film[y * width + x] += splat.value;
+                    }
+                }
+                (*pixel)[j] += col;
+            }
+            film
+        }).reduce(|| vec![Vec3::zero(); width * height], |mut a, b| {
+            for (x, y) in a.iter_mut().zip(b.iter()) {
+                *x += *y;
             }
-            col /= num_samples as f32;
-            (*pixel)[j] = col;
+            a
+        });
+        for (x, y) in splat_buf.iter_mut().zip(film.iter()) {
+            *x += *y;
         }
-    });
+    }
+
+    for (i, row) in color_buf.iter_mut().enumerate() {
+        for (j, col) in row.iter_mut().enumerate() {
+            *col += splat_buf[i * width + j];
+            *col = integrator.to_rgb(*col / num_samples as f32);
+        }
+    }
 
-    for i in (0 .. height).rev() {
-        for j in 0 .. width {
-            let col = color_buf[i][j];
-            let r = (255.99 * col[0].sqrt()) as i32;
-            let g = (255.99 * col[1].sqrt()) as i32;
-            let b = (255.99 * col[2].sqrt()) as i32;
-            write!(file, "{} {} {}\n", r, g, b).expect("Could not write to file");
+    for row in color_buf.iter().rev() {
+        for col in row.iter() {
+            let r = (255.99 * col[0].sqrt().min(1.0)) as i32;
+            let g = (255.99 * col[1].sqrt().min(1.0)) as i32;
+            let b = (255.99 * col[2].sqrt().min(1.0)) as i32;
+            writeln!(file, "{} {} {}", r, g, b).expect("Could not write to file");
         }
     }
 }
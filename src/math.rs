@@ -1,3 +1,6 @@
 pub mod aabb;
+pub mod noise;
+pub mod onb;
+pub mod spectrum;
 pub mod vec3;
 pub mod ray;
@@ -1,6 +1,21 @@
+#[cfg(feature = "bvh-stats")]
+use std::cell::Cell;
+
 use super::vec3::Vec3;
 use super::ray::Ray;
 
+#[cfg(feature = "bvh-stats")]
+thread_local! {
+    static HIT_TESTS: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Number of `Aabb::hit` tests run on the calling thread so far. The difference between two
+/// readings is what the traversals in between cost. Only counted with the `bvh-stats` feature.
+#[cfg(feature = "bvh-stats")]
+pub fn hit_tests() -> u64 {
+    HIT_TESTS.with(|tests| tests.get())
+}
+
 pub fn surrounding_box(box1: Aabb, box2: Aabb) -> Aabb {
     let min = Vec3::new(
         box1.min().x().min(box2.min().x()),
@@ -38,6 +53,8 @@ impl Aabb {
     }
 
     pub fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        #[cfg(feature = "bvh-stats")]
+        HIT_TESTS.with(|tests| tests.set(tests.get() + 1));
         let mut min = t_min;
         let mut max = t_max;
         let origin = r.origin();
@@ -0,0 +1,159 @@
+extern crate rand;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+
+use super::vec3::*;
+
+const POINT_COUNT: usize = 256;
+
+fn hermite(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Perlin gradient noise with random unit gradients on the lattice,
+/// blended with trilinear Hermite interpolation. Output is roughly in [-1, 1]. The lattice
+/// comes from `seed`, so the same seed gives the same noise in every render.
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let gradients = (0..POINT_COUNT)
+            .map(|_| unit_vector(Vec3::new(rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0))))
+            .collect();
+        let mut permutation = || {
+            let mut p: Vec<usize> = (0..POINT_COUNT).collect();
+            p.shuffle(&mut rng);
+            p
+        };
+        let perm_x = permutation();
+        let perm_y = permutation();
+        let perm_z = permutation();
+        Perlin {
+            gradients,
+            perm_x,
+            perm_y,
+            perm_z
+        }
+    }
+
+    fn hash(&self, i: i32, j: i32, k: i32) -> usize {
+        let mask = POINT_COUNT as i32 - 1;
+        self.perm_x[(i & mask) as usize] ^ self.perm_y[(j & mask) as usize] ^ self.perm_z[(k & mask) as usize]
+    }
+
+    pub fn noise(&self, p: Vec3) -> f32 {
+        let (fx, fy, fz) = (p.x().floor(), p.y().floor(), p.z().floor());
+        let (u, v, w) = (p.x() - fx, p.y() - fy, p.z() - fz);
+        let (i, j, k) = (fx as i32, fy as i32, fz as i32);
+        let (uu, vv, ww) = (hermite(u), hermite(v), hermite(w));
+
+        let mut accum = 0.0;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let gradient = self.gradients[self.hash(i + di, j + dj, k + dk)];
+                    let (a, b, c) = (di as f32, dj as f32, dk as f32);
+                    let offset = Vec3::new(u - a, v - b, w - c);
+                    accum += (a * uu + (1.0 - a) * (1.0 - uu))
+                        * (b * vv + (1.0 - b) * (1.0 - vv))
+                        * (c * ww + (1.0 - c) * (1.0 - ww))
+                        * dot(gradient, offset);
+                }
+            }
+        }
+        accum
+    }
+
+    /// Fractional Brownian motion: signed sum of octaves, each at double the frequency and half the weight.
+    pub fn fbm(&self, p: Vec3, octaves: u32) -> f32 {
+        let mut accum = 0.0;
+        let mut temp = p;
+        let mut weight = 1.0;
+        for _ in 0..octaves {
+            accum += weight * self.noise(temp);
+            weight *= 0.5;
+            temp = 2.0 * temp;
+        }
+        accum
+    }
+
+    /// Perlin's turbulence: like `fbm` but summing absolute values, giving creases at the zero crossings.
+    pub fn turbulence(&self, p: Vec3, octaves: u32) -> f32 {
+        let mut accum = 0.0;
+        let mut temp = p;
+        let mut weight = 1.0;
+        for _ in 0..octaves {
+            accum += weight * self.noise(temp).abs();
+            weight *= 0.5;
+            temp = 2.0 * temp;
+        }
+        accum
+    }
+
+    /// Worley cellular noise: distances to the nearest and second nearest feature points,
+    /// with one feature point per unit cell.
+    pub fn worley(&self, p: Vec3) -> (f32, f32) {
+        let (i, j, k) = (p.x().floor() as i32, p.y().floor() as i32, p.z().floor() as i32);
+        let mut f1 = f32::MAX;
+        let mut f2 = f32::MAX;
+        for di in -1..=1 {
+            for dj in -1..=1 {
+                for dk in -1..=1 {
+                    let (ci, cj, ck) = (i + di, j + dj, k + dk);
+                    let h = self.hash(ci, cj, ck);
+                    // Reuse the gradient table as a source of per-cell jitter in [0, 1].
+                    let g = self.gradients[h];
+                    let g2 = self.gradients[(h + 1) % POINT_COUNT];
+                    let jitter = Vec3::new(0.5 + 0.5 * g.x(), 0.5 + 0.5 * g.y(), 0.5 + 0.5 * g2.z());
+                    let feature = Vec3::new(ci as f32, cj as f32, ck as f32) + jitter;
+                    let d = (feature - p).length();
+                    if d < f1 {
+                        f2 = f1;
+                        f1 = d;
+                    }
+                    else if d < f2 {
+                        f2 = d;
+                    }
+                }
+            }
+        }
+        (f1, f2)
+    }
+}
+
+impl Default for Perlin {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeds_make_noise_reproducible() {
+        let p = Vec3::new(1.3, -2.7, 0.45);
+        assert_eq!(Perlin::new(7).noise(p), Perlin::new(7).noise(p));
+        assert_ne!(Perlin::new(7).noise(p), Perlin::new(8).noise(p));
+    }
+
+    #[test]
+    fn worley_distances_are_ordered() {
+        let perlin = Perlin::default();
+        for i in 0..100 {
+            let p = Vec3::new(0.37 * i as f32, 0.11 * i as f32, -0.23 * i as f32);
+            let (f1, f2) = perlin.worley(p);
+            assert!(f1 <= f2);
+            // Some feature point lies in the cell of p, so none is farther than its diagonal.
+            assert!(f1 <= 3.0f32.sqrt());
+        }
+    }
+}
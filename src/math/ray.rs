@@ -3,17 +3,25 @@ use super::vec3::Vec3;
 #[derive(Debug)]
 pub struct Ray {
     a: Vec3,
-    b: Vec3
+    b: Vec3,
+    /// Hero wavelength in nanometres when rendering spectrally, for wavelength dependent materials.
+    wavelength: Option<f32>
 }
 
 impl Ray {
     pub fn new(origin: Vec3, direction: Vec3) -> Ray {
         Ray {
             a: origin,
-            b: direction
+            b: direction,
+            wavelength: None
         }
     }
 
+    pub fn with_wavelength(mut self, wavelength: f32) -> Ray {
+        self.wavelength = Some(wavelength);
+        self
+    }
+
     pub fn origin(&self) -> Vec3 {
         self.a
     }
@@ -22,6 +30,10 @@ impl Ray {
         self.b
     }
 
+    pub fn wavelength(&self) -> Option<f32> {
+        self.wavelength
+    }
+
     pub fn point_at_parameter(&self, t :f32) -> Vec3 {
         self.a + t * self.b
     }
@@ -0,0 +1,280 @@
+use std::ops::Add;
+use std::ops::AddAssign;
+use std::ops::Div;
+use std::ops::Index;
+use std::ops::Mul;
+use std::sync::OnceLock;
+
+use super::vec3::Vec3;
+
+/// Shortest wavelength sampled, in nanometres.
+pub const LAMBDA_MIN: f32 = 380.0;
+/// Longest wavelength sampled, in nanometres.
+pub const LAMBDA_MAX: f32 = 720.0;
+/// Wavelengths carried by every path.
+pub const SAMPLES: usize = 4;
+
+/// Integrals of the color matching functions below over [LAMBDA_MIN, LAMBDA_MAX].
+const CIE_INTEGRAL: [f32; 3] = [106.760_72, 106.911_87, 106.825_33];
+/// XYZ of the D65 white point, which the equal energy spectrum is mapped to.
+const D65_WHITE: [f32; 3] = [0.950_47, 1.0, 1.088_83];
+
+/// Wavelengths followed by one path: a hero wavelength picked uniformly and the others spread
+/// evenly from it across the visible range (Wilkie et al. 2014). A path that reaches something
+/// that depends on wavelength, like a dispersive dielectric, can only follow the hero on from
+/// there and drops the rest with `terminate_secondary`.
+#[derive(Debug, Copy, Clone)]
+pub struct SampledWavelengths {
+    lambda: [f32; SAMPLES],
+    pdf: [f32; SAMPLES],
+}
+
+impl SampledWavelengths {
+    pub fn sample_uniform(u: f32) -> Self {
+        let mut lambda = [0.0; SAMPLES];
+        for (i, l) in lambda.iter_mut().enumerate() {
+            let offset = (u + i as f32 / SAMPLES as f32).fract();
+            *l = LAMBDA_MIN + offset * (LAMBDA_MAX - LAMBDA_MIN);
+        }
+        SampledWavelengths {
+            lambda,
+            pdf: [1.0 / (LAMBDA_MAX - LAMBDA_MIN); SAMPLES]
+        }
+    }
+
+    pub fn hero(&self) -> f32 {
+        self.lambda[0]
+    }
+
+    pub fn lambda(&self, i: usize) -> f32 {
+        self.lambda[i]
+    }
+
+    pub fn secondary_terminated(&self) -> bool {
+        self.pdf[1..].iter().all(|&pdf| pdf == 0.0)
+    }
+
+    /// Keeps only the hero wavelength, which now stands in for all of them.
+    pub fn terminate_secondary(&mut self) {
+        if self.secondary_terminated() {
+            return;
+        }
+        for pdf in self.pdf[1..].iter_mut() {
+            *pdf = 0.0;
+        }
+        self.pdf[0] /= SAMPLES as f32;
+    }
+
+    /// Monte Carlo estimate of the XYZ color of a spectrum from its values at these wavelengths.
+    /// Channels are scaled so the equal energy spectrum, which RGB white uplifts to, comes out as
+    /// the D65 white point and so as white in sRGB.
+    pub fn to_xyz(&self, s: SampledSpectrum) -> Vec3 {
+        let mut xyz = [0.0; 3];
+        for i in 0..SAMPLES {
+            if self.pdf[i] == 0.0 {
+                continue;
+            }
+            let cmf = cie_xyz(self.lambda[i]);
+            for (c, total) in xyz.iter_mut().enumerate() {
+                *total += s[i] * cmf[c] / self.pdf[i];
+            }
+        }
+        Vec3::new(
+            xyz[0] * D65_WHITE[0] / (CIE_INTEGRAL[0] * SAMPLES as f32),
+            xyz[1] * D65_WHITE[1] / (CIE_INTEGRAL[1] * SAMPLES as f32),
+            xyz[2] * D65_WHITE[2] / (CIE_INTEGRAL[2] * SAMPLES as f32))
+    }
+}
+
+/// Values of a spectrum at the wavelengths of a `SampledWavelengths`.
+#[derive(Debug, Copy, Clone)]
+pub struct SampledSpectrum {
+    e: [f32; SAMPLES],
+}
+
+impl SampledSpectrum {
+    pub fn new(value: f32) -> Self {
+        SampledSpectrum {
+            e: [value; SAMPLES]
+        }
+    }
+
+    pub fn zero() -> Self {
+        SampledSpectrum::new(0.0)
+    }
+
+    pub fn one() -> Self {
+        SampledSpectrum::new(1.0)
+    }
+
+    pub fn max_value(&self) -> f32 {
+        self.e.iter().cloned().fold(0.0, f32::max)
+    }
+
+    pub fn is_black(&self) -> bool {
+        self.e.iter().all(|&v| v <= 0.0)
+    }
+}
+
+impl Add for SampledSpectrum {
+    type Output = Self;
+
+    fn add(mut self, rhs: Self) -> Self {
+        self += rhs;
+        self
+    }
+}
+
+impl AddAssign for SampledSpectrum {
+    fn add_assign(&mut self, rhs: Self) {
+        for (a, b) in self.e.iter_mut().zip(rhs.e.iter()) {
+            *a += b;
+        }
+    }
+}
+
+impl Mul for SampledSpectrum {
+    type Output = Self;
+
+    fn mul(mut self, rhs: Self) -> Self {
+        for (a, b) in self.e.iter_mut().zip(rhs.e.iter()) {
+            *a *= b;
+        }
+        self
+    }
+}
+
+impl Mul<f32> for SampledSpectrum {
+    type Output = Self;
+
+    fn mul(mut self, rhs: f32) -> Self {
+        for a in self.e.iter_mut() {
+            *a *= rhs;
+        }
+        self
+    }
+}
+
+impl Div<f32> for SampledSpectrum {
+    type Output = Self;
+
+    fn div(self, rhs: f32) -> Self {
+        self * (1.0 / rhs)
+    }
+}
+
+impl Index<usize> for SampledSpectrum {
+    type Output = f32;
+
+    fn index(&self, i: usize) -> &f32 {
+        &self.e[i]
+    }
+}
+
+/// Smits' (1999) basis spectra over ten equal bins from LAMBDA_MIN to LAMBDA_MAX.
+const SMITS_WHITE: [f32; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const SMITS_CYAN: [f32; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const SMITS_MAGENTA: [f32; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const SMITS_YELLOW: [f32; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const SMITS_RED: [f32; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN: [f32; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const SMITS_BLUE: [f32; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+/// A smooth spectrum with roughly the color `rgb`, evaluated at the given wavelengths.
+pub fn uplift(rgb: Vec3, wavelengths: &SampledWavelengths) -> SampledSpectrum {
+    let mut s = SampledSpectrum::zero();
+    for i in 0..SAMPLES {
+        s.e[i] = uplift_at(rgb, wavelengths.lambda(i));
+    }
+    s
+}
+
+/// Value at `lambda` of a smooth spectrum with roughly the color `rgb`, using Smits' method:
+/// white plus the secondary and the primary color that make up the rest. White uplifts to
+/// the constant 1, so grey albedos and lights stay exactly grey.
+pub fn uplift_at(rgb: Vec3, lambda: f32) -> f32 {
+    let (r, g, b) = (rgb.x(), rgb.y(), rgb.z());
+    let x = (lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN);
+    let bin = ((x * 10.0).max(0.0) as usize).min(9);
+    if r <= g && r <= b {
+        r * SMITS_WHITE[bin] + if g <= b {
+            (g - r) * SMITS_CYAN[bin] + (b - g) * SMITS_BLUE[bin]
+        }
+        else {
+            (b - r) * SMITS_CYAN[bin] + (g - b) * SMITS_GREEN[bin]
+        }
+    }
+    else if g <= r && g <= b {
+        g * SMITS_WHITE[bin] + if r <= b {
+            (r - g) * SMITS_MAGENTA[bin] + (b - r) * SMITS_BLUE[bin]
+        }
+        else {
+            (b - g) * SMITS_MAGENTA[bin] + (r - b) * SMITS_RED[bin]
+        }
+    }
+    else {
+        b * SMITS_WHITE[bin] + if r <= g {
+            (r - b) * SMITS_YELLOW[bin] + (g - r) * SMITS_GREEN[bin]
+        }
+        else {
+            (g - b) * SMITS_YELLOW[bin] + (r - g) * SMITS_RED[bin]
+        }
+    }
+}
+
+/// Wavelengths `reflectance_to_rgb` evaluates at.
+const REFLECTANCE_SAMPLES: usize = 32;
+
+/// Linear sRGB of a reflectance spectrum `f(lambda)`, found by integrating it against the color
+/// matching functions. The constant 1 comes out white, like it does for `to_xyz`.
+pub fn reflectance_to_rgb<F: Fn(f32) -> f32>(f: F) -> Vec3 {
+    static TABLE: OnceLock<Vec<(f32, [f32; 3])>> = OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        let step = (LAMBDA_MAX - LAMBDA_MIN) / REFLECTANCE_SAMPLES as f32;
+        let lambdas: Vec<f32> = (0..REFLECTANCE_SAMPLES).map(|i| LAMBDA_MIN + (i as f32 + 0.5) * step).collect();
+        let mut white = [0.0; 3];
+        for &lambda in lambdas.iter() {
+            for (c, total) in white.iter_mut().enumerate() {
+                *total += cie_xyz(lambda)[c];
+            }
+        }
+        // Fold the scaling to the D65 white point into the weights.
+        lambdas.iter().map(|&lambda| {
+            let cmf = cie_xyz(lambda);
+            (lambda, [cmf[0] * D65_WHITE[0] / white[0], cmf[1] * D65_WHITE[1] / white[1], cmf[2] * D65_WHITE[2] / white[2]])
+        }).collect()
+    });
+    let mut xyz = [0.0; 3];
+    for &(lambda, weights) in table.iter() {
+        let value = f(lambda);
+        for (c, total) in xyz.iter_mut().enumerate() {
+            *total += value * weights[c];
+        }
+    }
+    xyz_to_rgb(Vec3::new(xyz[0], xyz[1], xyz[2]))
+}
+
+/// Piecewise Gaussian with different widths left and right of the peak.
+fn lobe(lambda: f32, mean: f32, sigma_left: f32, sigma_right: f32) -> f32 {
+    let t = (lambda - mean) / if lambda < mean { sigma_left } else { sigma_right };
+    (-0.5 * t * t).exp()
+}
+
+/// CIE 1931 2 degree color matching functions, using the multi-lobe fit of Wyman, Sloan and
+/// Shirley (2013).
+pub fn cie_xyz(lambda: f32) -> [f32; 3] {
+    [
+        1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7) - 0.065 * lobe(lambda, 501.1, 20.4, 26.2),
+        0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1),
+        1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8)
+    ]
+}
+
+/// Linear sRGB (D65) from CIE XYZ.
+pub fn xyz_to_rgb(xyz: Vec3) -> Vec3 {
+    let (x, y, z) = (xyz.x(), xyz.y(), xyz.z());
+    Vec3::new(
+        3.240_454_2 * x - 1.537_138_5 * y - 0.498_531_4 * z,
+        -0.969_266 * x + 1.876_010_8 * y + 0.041_556 * z,
+        0.055_643_4 * x - 0.204_025_9 * y + 1.057_225_2 * z)
+}
@@ -51,6 +51,19 @@ pub fn random_in_unit_disk() -> Vec3 {
     }
 }
 
+/// Cosine distributed direction on the +z hemisphere; its density is cos(theta) / pi.
+pub fn random_cosine_direction() -> Vec3 {
+    let mut rng = rand::thread_rng();
+    cosine_direction(rng.gen(), rng.gen())
+}
+
+/// `random_cosine_direction` for the uniform numbers `r1` and `r2`.
+pub fn cosine_direction(r1: f32, r2: f32) -> Vec3 {
+    let phi = 2.0 * f32::consts::PI * r1;
+    let r = r2.sqrt();
+    Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - r2).max(0.0).sqrt())
+}
+
 pub fn reflect(v: Vec3, n: Vec3) -> Vec3 {
     v - 2.0 * dot(v, n) * n
 }
@@ -1,4 +1,19 @@
 pub mod bvh;
+pub mod curve;
 pub mod camera;
 pub mod hitable;
+pub mod layered;
+pub mod lights;
 pub mod materials;
+pub mod mesh;
+pub mod microfacet;
+pub mod normal_map;
+pub mod point_cloud;
+pub mod principled;
+pub mod quad;
+pub mod scene;
+pub mod scene_file;
+pub mod subsurface;
+pub mod subdivision;
+pub mod texture;
+pub mod thin_film;
@@ -13,7 +13,7 @@ fn sort(list: &mut[Arc<dyn Hitable+Send+Sync>], axis: usize) {
         |a, b| {
             let result1 = a.bounding_box();
             let result2 = b.bounding_box();
-            if result1.result == false || result2.result == false {
+            if !result1.result || !result2.result {
                 panic!()
             }
             if result1.aabb.min()[axis] - result2.aabb.min()[axis] < 0.0 {
@@ -64,7 +64,7 @@ impl BvhNode {
 
         let left_result = left.bounding_box();
         let right_result = right.bounding_box();
-        if left_result.result == false && right_result.result == false {
+        if !left_result.result && !right_result.result {
             panic!();
         }
 
@@ -85,38 +85,29 @@ impl Hitable for BvhNode {
             let right_hit = self.right.hit(r, t_min, t_max, &mut right_rec);
             if left_hit && right_hit {
                 if left_rec.t < right_rec.t {
-                    rec.t = left_rec.t;
-                    rec.p = left_rec.p;
-                    rec.normal = left_rec.normal;
-                    rec.material = left_rec.material;
-                    return true;
+                    *rec = left_rec;
                 }
                 else {
-                    rec.t = right_rec.t;
-                    rec.p = right_rec.p;
-                    rec.normal = right_rec.normal;
-                    rec.material = right_rec.material;
-                    return true;
+                    *rec = right_rec;
                 }
+                return true;
             }
             else if left_hit {
-                rec.t = left_rec.t;
-                rec.p = left_rec.p;
-                rec.normal = left_rec.normal;
-                rec.material = left_rec.material;
+                *rec = left_rec;
                 return true;
             }
             else if right_hit {
-                rec.t = right_rec.t;
-                rec.p = right_rec.p;
-                rec.normal = right_rec.normal;
-                rec.material = right_rec.material;
+                *rec = right_rec;
                 return true;
             }
         }
         false
     }
 
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.aabb.hit(r, t_min, t_max) && (self.left.occluded(r, t_min, t_max) || self.right.occluded(r, t_min, t_max))
+    }
+
     fn bounding_box(&self) -> AabbResult {
         AabbResult {
             result: true,
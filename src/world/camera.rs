@@ -9,9 +9,10 @@ pub struct Camera {
     horizontal: Vec3,
     vertical: Vec3,
     lens_radius: f32,
+    focus_dist: f32,
     u: Vec3,
     v: Vec3,
-    _w: Vec3,
+    w: Vec3,
 }
 
 impl Camera {
@@ -28,9 +29,10 @@ impl Camera {
             horizontal: 2.0 * half_width * focus_dist * u,
             vertical: 2.0 * half_height * focus_dist * v,
             lens_radius: aperture / 2.0,
-            u: u,
-            v: v,
-            _w: w,
+            focus_dist,
+            u,
+            v,
+            w,
         }
     }
 
@@ -39,4 +41,40 @@ impl Camera {
         let offset = self.u * rd.x() + self.v * rd.y();
         Ray::new(self.origin + offset, self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset)
     }
-}
\ No newline at end of file
+
+    /// Film coordinates (s, t) in [0, 1) of the camera ray from `lens_point` through `p`,
+    /// or `None` when `p` is behind the camera or off the film.
+    pub fn project(&self, lens_point: Vec3, p: Vec3) -> Option<(f32, f32)> {
+        let d = p - lens_point;
+        let along = -dot(d, self.w);
+        if along <= 0.0 {
+            return None;
+        }
+        let on_focus_plane = lens_point + (self.focus_dist / along) * d - self.lower_left_corner;
+        let s = dot(on_focus_plane, self.horizontal) / self.horizontal.squared_length();
+        let t = dot(on_focus_plane, self.vertical) / self.vertical.squared_length();
+        if !(0.0..1.0).contains(&s) || !(0.0..1.0).contains(&t) {
+            return None;
+        }
+        Some((s, t))
+    }
+
+    /// Height of the film projected to the depth of `p`, i.e. how much of the scene the camera
+    /// sees vertically there.
+    pub fn view_height_at(&self, p: Vec3) -> f32 {
+        let along = -dot(p - self.origin, self.w);
+        self.vertical.length() * along.max(0.0) / self.focus_dist
+    }
+
+    /// Solid angle density of `get_ray` picking the direction from `lens_point` towards `p`
+    /// when (s, t) is uniform over the film: 1 / (A cos^3), with A the film area at unit distance.
+    /// This is also the camera's importance, which light tracing needs to splat onto the film.
+    pub fn pdf_direction(&self, lens_point: Vec3, p: Vec3) -> f32 {
+        if self.project(lens_point, p).is_none() {
+            return 0.0;
+        }
+        let cosine = -dot(unit_vector(p - lens_point), self.w);
+        let area = self.horizontal.length() * self.vertical.length() / (self.focus_dist * self.focus_dist);
+        1.0 / (area * cosine * cosine * cosine)
+    }
+}
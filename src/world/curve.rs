@@ -0,0 +1,332 @@
+use std::f32;
+use std::sync::Arc;
+
+use crate::math::aabb::*;
+use crate::math::onb::Onb;
+use crate::math::ray::Ray;
+use crate::math::vec3::*;
+use super::hitable::*;
+use super::materials::Material;
+
+const MAX_REFINEMENT: i32 = 10;
+
+/// Cross-section used when intersecting a curve.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CurveShape {
+    /// Flat strip that always faces the incoming ray. Cheap, and fine for thin strands and grass cards.
+    Ribbon,
+    /// Round tube, for strands thick enough that shading across their width is visible.
+    Cylinder,
+}
+
+fn bezier(p: &[Vec3; 4], t: f32) -> Vec3 {
+    let s = 1.0 - t;
+    s * s * s * p[0] + 3.0 * s * s * t * p[1] + 3.0 * s * t * t * p[2] + t * t * t * p[3]
+}
+
+fn bezier_derivative(p: &[Vec3; 4], t: f32) -> Vec3 {
+    let s = 1.0 - t;
+    3.0 * s * s * (p[1] - p[0]) + 6.0 * s * t * (p[2] - p[1]) + 3.0 * t * t * (p[3] - p[2])
+}
+
+/// Unit part of `v` across `tangent`, or some direction across it when `v` runs along it,
+/// e.g. for a ray looking straight down the strand.
+fn across(v: Vec3, tangent: Vec3) -> Vec3 {
+    let perpendicular = v - dot(v, tangent) * tangent;
+    if perpendicular.squared_length() > 1e-12 * v.squared_length() {
+        unit_vector(perpendicular)
+    }
+    else {
+        Onb::from_w(tangent).u()
+    }
+}
+
+/// Number of linear segments needed so the polyline stays within a small fraction of the
+/// strand width of the true curve (same bound as pbrt's curve refinement).
+fn segment_count(p: &[Vec3; 4], width: f32) -> usize {
+    let mut l0: f32 = 0.0;
+    for i in 0..2 {
+        let d = p[i] - 2.0 * p[i + 1] + p[i + 2];
+        l0 = l0.max(d.x().abs()).max(d.y().abs()).max(d.z().abs());
+    }
+    let eps = (width * 0.05).max(1e-6);
+    let r0 = (f32::consts::SQRT_2 * 6.0 * l0 / (8.0 * eps)).log2() * 0.5;
+    let depth = if r0.is_finite() { (r0.round() as i32).clamp(0, MAX_REFINEMENT) } else { 0 };
+    1 << depth
+}
+
+/// Cubic Bézier strand with a width that varies linearly from one end to the other.
+pub struct Curve {
+    control_points: [Vec3; 4],
+    width0: f32,
+    width1: f32,
+    shape: CurveShape,
+    points: Vec<Vec3>,
+    aabb: Aabb,
+    material: Arc<dyn Material>
+}
+
+impl Curve {
+    pub fn new(control_points: [Vec3; 4], width0: f32, width1: f32, shape: CurveShape, material: Arc<dyn Material>) -> Self {
+        let segments = segment_count(&control_points, width0.min(width1));
+        let points = (0..=segments)
+            .map(|i| bezier(&control_points, i as f32 / segments as f32))
+            .collect();
+
+        let half_width = 0.5 * width0.max(width1);
+        let mut min = control_points[0];
+        let mut max = control_points[0];
+        for p in control_points.iter() {
+            min = Vec3::new(min.x().min(p.x()), min.y().min(p.y()), min.z().min(p.z()));
+            max = Vec3::new(max.x().max(p.x()), max.y().max(p.y()), max.z().max(p.z()));
+        }
+        let pad = Vec3::new(half_width, half_width, half_width);
+
+        Curve {
+            control_points,
+            width0,
+            width1,
+            shape,
+            points,
+            aabb: Aabb::new(min - pad, max + pad),
+            material
+        }
+    }
+
+    fn width(&self, t: f32) -> f32 {
+        (1.0 - t) * self.width0 + t * self.width1
+    }
+
+    /// Point, normal, direction across the strand and v coordinate where `r` meets the curve
+    /// at ray parameter `t_hit` and curve parameter `t_curve`.
+    fn surface(&self, r: &Ray, t_hit: f32, t_curve: f32) -> (Vec3, Vec3, Vec3, f32) {
+        let tangent = unit_vector(bezier_derivative(&self.control_points, t_curve));
+        let p = r.point_at_parameter(t_hit);
+        let center = bezier(&self.control_points, t_curve);
+        let normal = match self.shape {
+            CurveShape::Ribbon => across(-r.direction(), tangent),
+            CurveShape::Cylinder => across(p - center, tangent)
+        };
+        let side = cross(tangent, normal);
+        let v = 0.5 + 0.5 * dot(p - center, side) / (0.5 * self.width(t_curve));
+        (p, normal, side, v.clamp(0.0, 1.0))
+    }
+
+    /// Ray parameter and curve parameter of the closest hit, or of the first one found
+    /// when `any_hit` is set.
+    fn intersect(&self, r: &Ray, t_min: f32, t_max: f32, any_hit: bool) -> Option<(f32, f32)> {
+        if !self.aabb.hit(r, t_min, t_max) {
+            return None;
+        }
+
+        let segments = self.points.len() - 1;
+        let d = r.direction();
+        let dd = dot(d, d);
+        let mut closest = t_max;
+        let mut found: Option<(f32, f32)> = None;
+
+        for i in 0..segments {
+            let a = self.points[i];
+            let axis = self.points[i + 1] - a;
+            let w0 = r.origin() - a;
+            let b = dot(d, axis);
+            let c = dot(axis, axis);
+            let dw = dot(d, w0);
+            let aw = dot(axis, w0);
+            let denom = dd * c - b * b;
+
+            // Closest approach between the ray and this segment of the polyline.
+            let mut w = if denom.abs() > 1e-12 { (dd * aw - b * dw) / denom } else { 0.0 };
+            w = w.clamp(0.0, 1.0);
+            let s = (b * w - dw) / dd;
+
+            let t_curve = (i as f32 + w) / segments as f32;
+            let radius = 0.5 * self.width(t_curve);
+            let offset = r.point_at_parameter(s) - (a + w * axis);
+            let h_sq = offset.squared_length();
+            if h_sq > radius * radius {
+                continue;
+            }
+
+            let candidates = match self.shape {
+                CurveShape::Ribbon => {
+                    // A ribbon turns to face every ray, so one leaving it would find it again
+                    // right away. Rays starting on this part of the strand can't hit it.
+                    let start = (aw / c).clamp(0.0, 1.0);
+                    let start_radius = 0.5 * self.width((i as f32 + start) / segments as f32);
+                    if (w0 - start * axis).squared_length() <= start_radius * start_radius * 1.0001 {
+                        continue;
+                    }
+                    // Only one crossing; the second never counts as closer.
+                    [s, f32::INFINITY]
+                },
+                CurveShape::Cylinder => {
+                    let axis_dir = unit_vector(axis);
+                    let d_perp = d - dot(d, axis_dir) * axis_dir;
+                    let d_perp_len = d_perp.length().max(1e-6);
+                    let half_chord = (radius * radius - h_sq).sqrt() / d_perp_len;
+                    // Rays leaving the tube (e.g. scattered from its surface) and rays through
+                    // a cut out near wall go on to the far wall.
+                    [s - half_chord, s + half_chord]
+                }
+            };
+            for &t_hit in candidates.iter() {
+                if t_hit <= t_min || t_hit >= closest {
+                    continue;
+                }
+                if self.material.cut_out(t_curve, self.surface(r, t_hit, t_curve).3, r.point_at_parameter(t_hit)) {
+                    continue;
+                }
+                closest = t_hit;
+                found = Some((t_hit, t_curve));
+                break;
+            }
+            if any_hit && found.is_some() {
+                break;
+            }
+        }
+        found
+    }
+}
+
+impl Hitable for Curve {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        if let Some((t_hit, t_curve)) = self.intersect(r, t_min, t_max, false) {
+            let (p, normal, side, v) = self.surface(r, t_hit, t_curve);
+            rec.t = t_hit;
+            rec.p = p;
+            rec.normal = normal;
+            rec.geometric_normal = normal;
+            rec.set_uv_derivatives(bezier_derivative(&self.control_points, t_curve), side * self.width(t_curve));
+            rec.u = t_curve;
+            rec.v = v;
+            rec.material = Some(Arc::clone(&self.material));
+            return true;
+        }
+        false
+    }
+
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.intersect(r, t_min, t_max, true).is_some()
+    }
+
+    fn bounding_box(&self) -> AabbResult {
+        AabbResult {
+            result: true,
+            aabb: self.aabb
+        }
+    }
+
+    fn has_medium(&self) -> bool {
+        self.material.medium().is_some()
+    }
+
+    fn material(&self) -> Option<&Arc<dyn Material>> {
+        Some(&self.material)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::materials::{Cutout, Lambertian};
+    use crate::world::texture::Texture;
+
+    /// Opaque along the second half of a strand only.
+    struct SecondHalf;
+
+    impl Texture for SecondHalf {
+        fn value(&self, u: f32, _v: f32, _p: Vec3) -> Vec3 {
+            if u < 0.5 { Vec3::zero() } else { Vec3::one() }
+        }
+    }
+
+    fn straight(shape: CurveShape) -> Curve {
+        let points = [Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 2.0, 0.0), Vec3::new(0.0, 3.0, 0.0)];
+        Curve::new(points, 0.2, 0.2, shape, Arc::new(Lambertian::new(Vec3::one())))
+    }
+
+    #[test]
+    fn ribbons_face_the_ray() {
+        let curve = straight(CurveShape::Ribbon);
+        let r = Ray::new(Vec3::new(0.05, 1.5, 2.0), Vec3::new(0.0, 0.0, -1.0));
+        let mut rec = HitRecord::new();
+        assert!(curve.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!((rec.t - 2.0).abs() < 1e-4);
+        assert!((rec.u - 0.5).abs() < 1e-4);
+        assert!((rec.v - 0.75).abs() < 1e-3);
+        assert!((rec.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-4);
+
+        let miss = Ray::new(Vec3::new(0.15, 1.5, 2.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(!curve.occluded(&miss, 0.001, f32::MAX));
+    }
+
+    #[test]
+    fn cylinders_hit_their_near_wall() {
+        let curve = straight(CurveShape::Cylinder);
+        let r = Ray::new(Vec3::new(0.0, 1.5, 2.0), Vec3::new(0.0, 0.0, -1.0));
+        let mut rec = HitRecord::new();
+        assert!(curve.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!((rec.t - 1.9).abs() < 1e-4);
+        assert!((rec.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-4);
+    }
+
+    #[test]
+    fn rays_along_the_strand_get_a_normal() {
+        for shape in [CurveShape::Ribbon, CurveShape::Cylinder].iter() {
+            let curve = straight(*shape);
+            let r = Ray::new(Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+            let mut rec = HitRecord::new();
+            assert!(curve.hit(&r, 0.001, f32::MAX, &mut rec));
+            assert!((rec.normal.length() - 1.0).abs() < 1e-4, "{:?}", shape);
+            assert!(dot(rec.normal, Vec3::new(0.0, 1.0, 0.0)).abs() < 1e-4, "{:?}", shape);
+        }
+    }
+
+    #[test]
+    fn cut_out_strands_let_rays_through() {
+        let material = Arc::new(Cutout::new(Arc::new(Lambertian::new(Vec3::one())), Arc::new(SecondHalf), 0.5));
+        let points = [Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 2.0, 0.0), Vec3::new(0.0, 3.0, 0.0)];
+        let curve = Curve::new(points, 0.2, 0.2, CurveShape::Cylinder, material);
+        let through = Ray::new(Vec3::new(0.0, 0.75, 2.0), Vec3::new(0.0, 0.0, -1.0));
+        let mut rec = HitRecord::new();
+        assert!(!curve.hit(&through, 0.001, f32::MAX, &mut rec));
+        assert!(!curve.occluded(&through, 0.001, f32::MAX));
+        let blocked = Ray::new(Vec3::new(0.0, 2.25, 2.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(curve.hit(&blocked, 0.001, f32::MAX, &mut rec));
+        assert!(curve.occluded(&blocked, 0.001, f32::MAX));
+    }
+
+    #[test]
+    fn rays_leaving_a_ribbon_do_not_hit_it_again() {
+        let curve = straight(CurveShape::Ribbon);
+        let r = Ray::new(Vec3::new(0.05, 1.5, 2.0), Vec3::new(0.0, 0.0, -1.0));
+        let mut rec = HitRecord::new();
+        assert!(curve.hit(&r, 0.001, f32::MAX, &mut rec));
+        for &direction in [Vec3::new(-0.5, 0.0, 0.866), Vec3::new(0.5, 0.0, 0.866), Vec3::new(-0.5, 0.0, -0.866)].iter() {
+            let scattered = Ray::new(rec.p, direction);
+            let mut again = HitRecord::new();
+            assert!(!curve.hit(&scattered, 0.001, f32::MAX, &mut again), "{:?}", direction);
+            assert!(!curve.occluded(&scattered, 0.001, f32::MAX), "{:?}", direction);
+        }
+    }
+
+    #[test]
+    fn cut_out_near_walls_show_the_far_wall() {
+        /// Opaque on the far side of the tube only.
+        struct Back;
+
+        impl Texture for Back {
+            fn value(&self, _u: f32, _v: f32, p: Vec3) -> Vec3 {
+                if p.z() < 0.0 { Vec3::one() } else { Vec3::zero() }
+            }
+        }
+
+        let material = Arc::new(Cutout::new(Arc::new(Lambertian::new(Vec3::one())), Arc::new(Back), 0.5));
+        let points = [Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 2.0, 0.0), Vec3::new(0.0, 3.0, 0.0)];
+        let curve = Curve::new(points, 0.2, 0.2, CurveShape::Cylinder, material);
+        let mut rec = HitRecord::new();
+        assert!(curve.hit(&Ray::new(Vec3::new(0.0, 1.5, 2.0), Vec3::new(0.0, 0.0, -1.0)), 0.001, f32::MAX, &mut rec));
+        assert!((rec.t - 2.1).abs() < 1e-4);
+    }
+}
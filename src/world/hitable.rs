@@ -1,16 +1,41 @@
+extern crate rand;
+use rand::Rng;
+
 use crate::math::aabb::*;
+use crate::math::onb::Onb;
 use crate::math::vec3::Vec3;
 use crate::math::ray::Ray;
 use crate::math::vec3::dot as dot;
+use crate::math::vec3::unit_vector;
 use super::materials::Material;
 
+use std::f32;
 use std::vec::Vec;
 use std::sync::Arc;
 
+#[derive(Clone)]
 pub struct HitRecord {
     pub t: f32,
     pub p: Vec3,
+    /// Shading normal, which is what materials use.
     pub normal: Vec3,
+    /// Normal of the actual surface, before any interpolation, on the same side as `normal`.
+    pub geometric_normal: Vec3,
+    /// Direction of the surface or fiber at the hit point, zero where the primitive has none.
+    /// Along `dpdu` wherever that is set.
+    pub tangent: Vec3,
+    /// Direction of `dpdv`, zero where the primitive has none. Together with `tangent` and
+    /// `normal` it makes the frame tangent space normal maps are given in.
+    pub bitangent: Vec3,
+    /// How `p` moves with `u` and with `v`, for bump mapping. Zero where there are no uvs.
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    /// Surface parameterization used for texture lookups.
+    pub u: f32,
+    pub v: f32,
+    /// Color of the point or vertex that was hit, which diffuse albedos are multiplied by.
+    /// White where the primitive has none.
+    pub color: Vec3,
     pub material: Option<Arc<dyn Material>>
 }
 
@@ -20,11 +45,39 @@ impl HitRecord {
             t: 0.0,
             p: Vec3::zero(),
             normal: Vec3::zero(),
+            geometric_normal: Vec3::zero(),
+            tangent: Vec3::zero(),
+            bitangent: Vec3::zero(),
+            dpdu: Vec3::zero(),
+            dpdv: Vec3::zero(),
+            u: 0.0,
+            v: 0.0,
+            color: Vec3::one(),
             material: None
         }
     }
 }
 
+impl HitRecord {
+    /// Sets `dpdu` and `dpdv`, and `tangent` and `bitangent` along them.
+    pub fn set_uv_derivatives(&mut self, dpdu: Vec3, dpdv: Vec3) {
+        let direction = |d: Vec3| {
+            let length = d.length();
+            if length > 0.0 { d / length } else { Vec3::zero() }
+        };
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+        self.tangent = direction(dpdu);
+        self.bitangent = direction(dpdv);
+    }
+}
+
+impl Default for HitRecord {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct AabbResult {
     pub result: bool,
     pub aabb: Aabb,
@@ -33,6 +86,65 @@ pub struct AabbResult {
 pub trait Hitable: Send+Sync {
     fn hit(&self, r: &Ray, t_min: f32, t_max:f32, rec: &mut HitRecord) -> bool;
     fn bounding_box(&self) -> AabbResult;
+
+    /// Whether anything at all lies along `r` between `t_min` and `t_max`. Stops at the first
+    /// hit found and fills in no record, which is all shadow rays need.
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        let mut rec = HitRecord::new();
+        self.hit(r, t_min, t_max, &mut rec)
+    }
+
+    /// Solid angle density with which `random(origin)` picks `direction`, for use as a light.
+    fn pdf_value(&self, _origin: Vec3, _direction: Vec3) -> f32 {
+        0.0
+    }
+
+    /// Vector from `origin` to a random point on the surface, seen from `origin`.
+    fn random(&self, _origin: Vec3) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
+
+    /// Uniformly distributed point on the surface, filled in as if a ray had hit it there,
+    /// together with its area density. Used to start paths on lights.
+    fn sample_surface(&self) -> Option<(HitRecord, f32)> {
+        None
+    }
+
+    /// Area density with which `sample_surface` picks `p`.
+    fn surface_pdf(&self, _p: Vec3) -> f32 {
+        0.0
+    }
+
+    /// Whether this primitive should be sampled as a light.
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    /// Whether this primitive's material fills it with a `Medium`.
+    fn has_medium(&self) -> bool {
+        false
+    }
+
+    /// The material every hit on this primitive reports, if there is just one.
+    fn material(&self) -> Option<&Arc<dyn Material>> {
+        None
+    }
+}
+
+/// Longitude/latitude uvs of a point on the unit sphere, with v = 0 at the bottom pole.
+pub fn sphere_uv(p: Vec3) -> (f32, f32) {
+    let theta = (-p.y()).clamp(-1.0, 1.0).acos();
+    let phi = (-p.z()).atan2(p.x()) + f32::consts::PI;
+    (phi / (2.0 * f32::consts::PI), theta / f32::consts::PI)
+}
+
+/// Derivatives of the point on a sphere of `radius` with respect to the `sphere_uv`s at `p` on
+/// the unit sphere. `dpdu` vanishes at the poles.
+fn sphere_derivatives(p: Vec3, radius: f32) -> (Vec3, Vec3) {
+    let ring = (p.x() * p.x() + p.z() * p.z()).sqrt().max(1e-6);
+    let dpdu = 2.0 * f32::consts::PI * radius * Vec3::new(p.z(), 0.0, -p.x());
+    let dpdv = f32::consts::PI * radius * Vec3::new(-p.y() * p.x() / ring, ring, -p.y() * p.z() / ring);
+    (dpdu, dpdv)
 }
 
 pub struct Sphere {
@@ -47,31 +159,55 @@ impl Sphere {
             center, radius, material
         }
     }
-}
 
-impl Hitable for Sphere {
-    fn hit(&self, r: &Ray, t_min: f32, t_max:f32, rec: &mut HitRecord) -> bool {
+    /// Ray parameters where `r` crosses the sphere, nearest first. Uses the form of the
+    /// quadratic formula that doesn't cancel, so a ray leaving the surface gets a root at
+    /// (almost exactly) zero rather than one that can land on either side of `t_min`.
+    fn roots(&self, r: &Ray) -> Option<(f32, f32)> {
         let oc = r.origin() - self.center;
         let a = dot(r.direction(), r.direction());
         let b = dot(oc, r.direction());
         let c = dot(oc, oc) - self.radius * self.radius;
         let d = b * b - a * c;
-        if d > 0.0 {
-            let temp = (-b - d.sqrt()) / a;
-            if temp < t_max && temp > t_min {
-                rec.t = temp;
-                rec.p = r.point_at_parameter(temp);
-                rec.normal = (rec.p - self.center) / self.radius;
-                rec.material = Some(Arc::clone(&self.material));
-                return true
-            }
-            let temp = (-b + d.sqrt()) / a;
-            if temp < t_max && temp > t_min {
-                rec.t = temp;
-                rec.p = r.point_at_parameter(temp);
-                rec.normal = (rec.p - self.center) / self.radius;
-                rec.material = Some(Arc::clone(&self.material));
-                return true
+        if d <= 0.0 {
+            return None;
+        }
+        let q = if b > 0.0 { -(b + d.sqrt()) } else { -b + d.sqrt() };
+        if q == 0.0 {
+            return None;
+        }
+        let (t0, t1) = (q / a, c / q);
+        Some(if t0 < t1 { (t0, t1) } else { (t1, t0) })
+    }
+
+    /// Whether the material is cut out where `r` crosses the sphere at `t`.
+    fn cut_out(&self, r: &Ray, t: f32) -> bool {
+        let outward = unit_vector(r.point_at_parameter(t) - self.center);
+        let (u, v) = sphere_uv(outward);
+        self.material.cut_out(u, v, self.center + self.radius.abs() * outward)
+    }
+}
+
+impl Hitable for Sphere {
+    fn hit(&self, r: &Ray, t_min: f32, t_max:f32, rec: &mut HitRecord) -> bool {
+        if let Some((near, far)) = self.roots(r) {
+            for &temp in [near, far].iter() {
+                if temp < t_max && temp > t_min && !self.cut_out(r, temp) {
+                    rec.t = temp;
+                    // Snap onto the surface: from a distant origin the root alone can leave the
+                    // point far enough inside for secondary rays to hit the sphere again.
+                    let outward = unit_vector(r.point_at_parameter(temp) - self.center);
+                    rec.p = self.center + self.radius.abs() * outward;
+                    rec.normal = (rec.p - self.center) / self.radius;
+                    rec.geometric_normal = rec.normal;
+                    let (dpdu, dpdv) = sphere_derivatives(outward, self.radius.abs());
+                    rec.set_uv_derivatives(dpdu, dpdv);
+                    let (u, v) = sphere_uv(outward);
+                    rec.u = u;
+                    rec.v = v;
+                    rec.material = Some(Arc::clone(&self.material));
+                    return true
+                }
             }
         }
         false
@@ -86,6 +222,79 @@ impl Hitable for Sphere {
             )
         }
     }
+
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        match self.roots(r) {
+            Some((near, far)) => [near, far].iter().any(|&t| t < t_max && t > t_min && !self.cut_out(r, t)),
+            None => false
+        }
+    }
+
+    /// Uniform over the cone the sphere subtends; zero from inside the sphere.
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f32 {
+        let mut rec = HitRecord::new();
+        if !self.hit(&Ray::new(origin, direction), 0.001, f32::MAX, &mut rec) {
+            return 0.0;
+        }
+        let distance_squared = (self.center - origin).squared_length();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return 0.0;
+        }
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        1.0 / (2.0 * f32::consts::PI * (1.0 - cos_theta_max))
+    }
+
+    fn random(&self, origin: Vec3) -> Vec3 {
+        let mut rng = rand::thread_rng();
+        let direction = self.center - origin;
+        let distance_squared = direction.squared_length();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return direction;
+        }
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        let z = 1.0 + rng.gen::<f32>() * (cos_theta_max - 1.0);
+        let phi = 2.0 * f32::consts::PI * rng.gen::<f32>();
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        Onb::from_w(direction).local(Vec3::new(r * phi.cos(), r * phi.sin(), z))
+    }
+
+    fn sample_surface(&self) -> Option<(HitRecord, f32)> {
+        let mut rng = rand::thread_rng();
+        let z = 1.0 - 2.0 * rng.gen::<f32>();
+        let phi = 2.0 * f32::consts::PI * rng.gen::<f32>();
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let direction = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+        let mut rec = HitRecord::new();
+        rec.p = self.center + self.radius.abs() * direction;
+        rec.normal = (rec.p - self.center) / self.radius;
+        rec.geometric_normal = rec.normal;
+        let (dpdu, dpdv) = sphere_derivatives(direction, self.radius.abs());
+        rec.set_uv_derivatives(dpdu, dpdv);
+        let (u, v) = sphere_uv(direction);
+        rec.u = u;
+        rec.v = v;
+        rec.material = Some(Arc::clone(&self.material));
+        let pdf = self.surface_pdf(rec.p);
+        Some((rec, pdf))
+    }
+
+    fn surface_pdf(&self, _p: Vec3) -> f32 {
+        1.0 / (4.0 * f32::consts::PI * self.radius * self.radius)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    fn has_medium(&self) -> bool {
+        self.material.medium().is_some()
+    }
+
+    fn material(&self) -> Option<&Arc<dyn Material>> {
+        Some(&self.material)
+    }
 }
 
 #[allow(dead_code)]
@@ -104,40 +313,42 @@ impl HitableList {
 
 impl Hitable for HitableList {
     fn hit(&self, r: &Ray, t_min: f32, t_max:f32, rec: &mut HitRecord) -> bool {
-        let mut temp_rec = HitRecord::new();
         let mut hit = false;
         let mut closest = t_max;
         for l in self.list.iter() {
+            // Fresh for every primitive, so fields one sets and another leaves alone don't leak.
+            let mut temp_rec = HitRecord::new();
             if l.hit(r, t_min, closest, &mut temp_rec) {
                 hit = true;
                 closest = temp_rec.t;
-                rec.t = temp_rec.t;
-                rec.p = temp_rec.p;
-                rec.normal = temp_rec.normal;
-                rec.material = temp_rec.material.as_ref().and_then(|x| Some(Arc::clone(x)));
+                *rec = temp_rec.clone();
             }
         }
         hit
     }
 
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.list.iter().any(|l| l.occluded(r, t_min, t_max))
+    }
+
     fn bounding_box(&self) -> AabbResult {
         let mut temp = AabbResult {
             result: false,
             aabb: Aabb::new(Vec3::zero(), Vec3::zero())
         };
 
-        if self.list.len() < 1 {
+        if self.list.is_empty() {
             return temp
         }
 
         let first_result = self.list[0].bounding_box();
-        if first_result.result == false {
+        if !first_result.result {
             return temp;
         }
 
         for l in self.list.iter() {
             let result = l.bounding_box();
-            if result.result == true {
+            if result.result {
                 temp.aabb = surrounding_box(temp.aabb, result.aabb);
             }
         }
@@ -147,3 +358,32 @@ impl Hitable for HitableList {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::vec3::cross;
+    use crate::world::materials::Lambertian;
+
+    #[test]
+    fn sphere_uvs_run_around_and_up() {
+        let (_, v) = sphere_uv(Vec3::new(0.0, -1.0, 0.0));
+        assert!(v.abs() < 1e-5);
+        let (_, v) = sphere_uv(Vec3::new(0.0, 1.0, 0.0));
+        assert!((v - 1.0).abs() < 1e-5);
+        let (u, v) = sphere_uv(Vec3::new(1.0, 0.0, 0.0));
+        assert!((u - 0.5).abs() < 1e-5 && (v - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn spheres_report_uvs_at_hits() {
+        let sphere = Sphere::new(Vec3::new(0.0, 0.0, -3.0), 1.0, Arc::new(Lambertian::new(Vec3::one())));
+        let mut rec = HitRecord::new();
+        assert!(sphere.hit(&Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0)), 0.001, f32::MAX, &mut rec));
+        assert!((rec.t - 2.0).abs() < 1e-5);
+        assert!((rec.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-5);
+        assert!((rec.u - 0.25).abs() < 1e-5 && (rec.v - 0.5).abs() < 1e-5);
+        // u grows along the tangent and v along the bitangent, so the frame matches the normal.
+        assert!((cross(rec.tangent, rec.bitangent) - rec.normal).length() < 1e-4);
+    }
+}
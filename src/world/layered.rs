@@ -0,0 +1,254 @@
+extern crate rand;
+use rand::Rng;
+
+use std::sync::Arc;
+
+use crate::math::ray::Ray;
+use crate::math::vec3::*;
+use super::hitable::HitRecord;
+use super::materials::*;
+use super::subsurface::Medium;
+
+/// Internal reflections off the underside of a coat followed before giving up on a path.
+const INTERNAL_BOUNCES: usize = 8;
+
+/// Smooth dielectric clearcoat over any other material, e.g. varnished wood or car paint.
+/// Layers are evaluated stochastically: sampling either reflects off the coat with its Fresnel
+/// reflectance or refracts in and random walks between `base` and the underside of the coat
+/// until the path gets back out, and `eval` estimates the same walk with one random path, so
+/// light sampling sees the light trapped under the coat as well. `pdf` only accounts for the
+/// direct trip through the coat, which is enough for MIS weights. Layers stack by using another
+/// `Coated` as the base.
+pub struct Coated {
+    base: Arc<dyn Material>,
+    refraction_index: f32
+}
+
+impl Coated {
+    pub fn new(base: Arc<dyn Material>, refraction_index: f32) -> Self {
+        Coated {
+            base,
+            refraction_index
+        }
+    }
+
+    fn reflectance(&self, cosine: f32) -> f32 {
+        shclick(cosine, self.refraction_index)
+    }
+
+    /// Outside direction `w` bent into the coat, still pointing away from the base.
+    fn inside(&self, w: Vec3, normal: Vec3) -> Vec3 {
+        let cosine = dot(w, normal);
+        let tangential = (w - cosine * normal) / self.refraction_index;
+        tangential + (1.0 - tangential.squared_length()).max(0.0).sqrt() * normal
+    }
+
+    /// Inverse of `inside`, or `None` where the coat reflects it back in totally.
+    fn outside(&self, w: Vec3, normal: Vec3) -> Option<Vec3> {
+        let tangential = (w - dot(w, normal) * normal) * self.refraction_index;
+        let sin2 = tangential.squared_length();
+        if sin2 >= 1.0 {
+            return None;
+        }
+        Some(tangential + (1.0 - sin2).sqrt() * normal)
+    }
+
+    /// Chance of light heading up along `w` under the coat being reflected back down.
+    fn internal_reflectance(&self, w: Vec3, normal: Vec3) -> f32 {
+        match self.outside(w, normal) {
+            Some(out) => self.reflectance(dot(out, normal)),
+            None => 1.0
+        }
+    }
+
+    /// Samples `base` for light arriving at it from `w` under the coat, keeping the wavelength
+    /// of `ray_in`. Returns `None` unless the scattered ray heads back up.
+    fn sample_base(&self, ray_in: &Ray, rec: &HitRecord, w: Vec3, normal: Vec3) -> Option<(ScatterRecord, Vec3)> {
+        let mut base_ray = Ray::new(rec.p + w, -w);
+        if let Some(lambda) = ray_in.wavelength() {
+            base_ray = base_ray.with_wavelength(lambda);
+        }
+        let srec = self.base.sample(&base_ray, rec)?;
+        let up = unit_vector(srec.scattered.direction());
+        if dot(up, normal) <= 0.0 {
+            return None;
+        }
+        Some((srec, up))
+    }
+}
+
+/// `w` reflected off the underside of the coat, turned around to point away from the base again.
+fn bounce(w: Vec3, normal: Vec3) -> Vec3 {
+    2.0 * dot(w, normal) * normal - w
+}
+
+impl Material for Coated {
+    fn sample(&self, ray_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let wo = -unit_vector(ray_in.direction());
+        let normal = facing_normal(wo, rec);
+        let mut rng = rand::thread_rng();
+        if rng.gen::<f32>() < self.reflectance(dot(wo, normal)) {
+            return Some(ScatterRecord::specular(Vec3::one(), Ray::new(rec.p, reflect(-wo, normal))));
+        }
+        // The Fresnel transmittance on the way in cancels against the chance of getting here,
+        // and the one on the way out against the chance of leaving.
+        let mut w = self.inside(wo, normal);
+        let mut attenuation = Vec3::one();
+        let mut is_specular = true;
+        for _ in 0..INTERNAL_BOUNCES {
+            let (srec, up) = self.sample_base(ray_in, rec, w, normal)?;
+            attenuation = attenuation * srec.attenuation;
+            is_specular = is_specular && srec.is_specular;
+            if rng.gen::<f32>() >= self.internal_reflectance(up, normal) {
+                let wi = self.outside(up, normal)?;
+                if is_specular {
+                    return Some(ScatterRecord::specular(attenuation, Ray::new(rec.p, wi)));
+                }
+                return Some(ScatterRecord {
+                    attenuation,
+                    scattered: Ray::new(rec.p, wi),
+                    pdf: self.pdf(wo, wi, rec),
+                    is_specular: false
+                });
+            }
+            w = bounce(up, normal);
+        }
+        None
+    }
+
+    /// Fresnel transmittance in and out times the base's value at the refracted directions,
+    /// with a one path estimate of the light that bounced off the underside of the coat first.
+    /// The 1 / eta^2 is radiance spreading back out over the wider outside solid angle.
+    fn eval(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> Vec3 {
+        let normal = facing_normal(wo, rec);
+        let (cos_o, cos_i) = (dot(wo, normal), dot(wi, normal));
+        if cos_o <= 0.0 || cos_i <= 0.0 {
+            return Vec3::zero();
+        }
+        let (wo_inside, wi_inside) = (self.inside(wo, normal), self.inside(wi, normal));
+        let mut f = self.base.eval(wo_inside, wi_inside, rec);
+        let probe = Ray::new(rec.p + wo, -wo);
+        let mut w = wo_inside;
+        let mut throughput = Vec3::one();
+        for _ in 1..INTERNAL_BOUNCES {
+            let (srec, up) = match self.sample_base(&probe, rec, w, normal) {
+                Some(sampled) => sampled,
+                None => break
+            };
+            throughput = throughput * srec.attenuation * self.internal_reflectance(up, normal);
+            if throughput.x() <= 0.0 && throughput.y() <= 0.0 && throughput.z() <= 0.0 {
+                break;
+            }
+            w = bounce(up, normal);
+            f += throughput * self.base.eval(w, wi_inside, rec);
+        }
+        let transmittance = (1.0 - self.reflectance(cos_o)) * (1.0 - self.reflectance(cos_i));
+        f * (transmittance / (self.refraction_index * self.refraction_index))
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> f32 {
+        let normal = facing_normal(wo, rec);
+        let (cos_o, cos_i) = (dot(wo, normal), dot(wi, normal));
+        if cos_o <= 0.0 || cos_i <= 0.0 {
+            return 0.0;
+        }
+        let wi_inside = self.inside(wi, normal);
+        // Solid angle inside the coat per solid angle outside.
+        let jacobian = cos_i / (self.refraction_index * self.refraction_index * dot(wi_inside, normal));
+        (1.0 - self.reflectance(cos_o)) * self.base.pdf(self.inside(wo, normal), wi_inside, rec) * jacobian
+    }
+
+    /// Light from an emissive base, less what the coat reflects back down.
+    fn emitted(&self, ray_in: &Ray, rec: &HitRecord) -> Vec3 {
+        let wo = -unit_vector(ray_in.direction());
+        let transmittance = 1.0 - self.reflectance(dot(wo, facing_normal(wo, rec)));
+        self.base.emitted(ray_in, rec) * transmittance
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.base.is_emissive()
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.base.is_dispersive()
+    }
+
+    fn medium(&self) -> Option<Medium> {
+        self.base.medium()
+    }
+
+    fn cut_out(&self, u: f32, v: f32, p: Vec3) -> bool {
+        self.base.cut_out(u, v, p)
+    }
+}
+
+/// Stochastic mix of two materials, taking `b` with probability `weight`; e.g. sparse metallic
+/// flakes in a diffuse paint.
+pub struct Blend {
+    a: Arc<dyn Material>,
+    b: Arc<dyn Material>,
+    weight: f32
+}
+
+impl Blend {
+    pub fn new(a: Arc<dyn Material>, b: Arc<dyn Material>, weight: f32) -> Self {
+        Blend {
+            a,
+            b,
+            weight: weight.clamp(0.0, 1.0)
+        }
+    }
+}
+
+impl Material for Blend {
+    fn sample(&self, ray_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let picked = if rand::thread_rng().gen::<f32>() < self.weight { &self.b } else { &self.a };
+        let srec = picked.sample(ray_in, rec)?;
+        if srec.is_specular {
+            return Some(srec);
+        }
+        // Weigh against the whole mixture, which could have picked this direction either way.
+        let wo = -unit_vector(ray_in.direction());
+        let wi = unit_vector(srec.scattered.direction());
+        let pdf = self.pdf(wo, wi, rec);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(ScatterRecord {
+            attenuation: self.eval(wo, wi, rec) * (dot(wi, rec.normal).abs() / pdf),
+            scattered: srec.scattered,
+            pdf,
+            is_specular: false
+        })
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> Vec3 {
+        self.a.eval(wo, wi, rec) * (1.0 - self.weight) + self.b.eval(wo, wi, rec) * self.weight
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> f32 {
+        self.a.pdf(wo, wi, rec) * (1.0 - self.weight) + self.b.pdf(wo, wi, rec) * self.weight
+    }
+
+    fn emitted(&self, ray_in: &Ray, rec: &HitRecord) -> Vec3 {
+        self.a.emitted(ray_in, rec) * (1.0 - self.weight) + self.b.emitted(ray_in, rec) * self.weight
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.a.is_emissive() || self.b.is_emissive()
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.a.is_dispersive() || self.b.is_dispersive()
+    }
+
+    /// The first of the two that has one.
+    fn medium(&self) -> Option<Medium> {
+        self.a.medium().or_else(|| self.b.medium())
+    }
+
+    /// Only holes in both materials go through the blend.
+    fn cut_out(&self, u: f32, v: f32, p: Vec3) -> bool {
+        self.a.cut_out(u, v, p) && self.b.cut_out(u, v, p)
+    }
+}
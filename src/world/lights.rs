@@ -0,0 +1,185 @@
+extern crate rand;
+use rand::Rng;
+
+use std::f32;
+use std::sync::Arc;
+
+use crate::math::vec3::*;
+use crate::math::ray::Ray;
+use super::hitable::*;
+
+/// Emissive primitives of a scene, sampled uniformly by integrators doing light sampling.
+pub struct LightList {
+    lights: Vec<Arc<dyn Hitable+Send+Sync>>
+}
+
+impl LightList {
+    pub fn new(lights: Vec<Arc<dyn Hitable+Send+Sync>>) -> Self {
+        LightList {
+            lights
+        }
+    }
+
+    /// Picks out the primitives whose material emits.
+    pub fn from_hitables(hitables: &[Arc<dyn Hitable+Send+Sync>]) -> Self {
+        LightList::new(hitables.iter().filter(|h| h.is_emissive()).cloned().collect())
+    }
+
+    pub fn len(&self) -> usize {
+        self.lights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    /// Density of `random` in solid angle: the average over all lights, since any of them
+    /// may have produced `direction`.
+    pub fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f32 {
+        if self.lights.is_empty() {
+            return 0.0;
+        }
+        let sum: f32 = self.lights.iter().map(|l| l.pdf_value(origin, direction)).sum();
+        sum / self.lights.len() as f32
+    }
+
+    /// Vector from `origin` to a point on a uniformly chosen light.
+    pub fn random(&self, origin: Vec3) -> Vec3 {
+        let mut rng = rand::thread_rng();
+        let index = rng.gen_range(0, self.lights.len());
+        self.lights[index].random(origin)
+    }
+
+    /// Point on a uniformly chosen light with its area density, which includes the choice of light.
+    pub fn sample_surface(&self) -> Option<(HitRecord, f32)> {
+        if self.lights.is_empty() {
+            return None;
+        }
+        let mut rng = rand::thread_rng();
+        let index = rng.gen_range(0, self.lights.len());
+        self.lights[index].sample_surface().map(|(rec, pdf)| (rec, pdf / self.lights.len() as f32))
+    }
+
+    /// Area density with which `sample_surface` would pick the point `r` hits at `t`. The hit
+    /// doesn't say which primitive it came from, so every light `r` meets at that distance counts.
+    pub fn surface_pdf(&self, r: &Ray, t: f32) -> f32 {
+        if self.lights.is_empty() {
+            return 0.0;
+        }
+        let p = r.point_at_parameter(t);
+        let tolerance = 1e-3 * t.max(1.0);
+        let sum: f32 = self.lights.iter()
+            .filter(|l| {
+                let mut rec = HitRecord::new();
+                l.hit(r, t - tolerance, t + tolerance, &mut rec)
+            })
+            .map(|l| l.surface_pdf(p))
+            .sum();
+        sum / self.lights.len() as f32
+    }
+}
+
+/// Light arriving at a point from a `PunctualLight`.
+pub struct LightSample {
+    /// Unit direction from the shaded point towards the light.
+    pub wi: Vec3,
+    /// Distance to the light along `wi`, infinite for directional lights.
+    pub distance: f32,
+    /// Radiance scaled so that `f * radiance * |cos|` is the light's contribution.
+    pub radiance: Vec3,
+}
+
+/// Lights without geometry: rays can never hit them, so they are reached by shadow rays only.
+#[derive(Debug, Copy, Clone)]
+pub enum PunctualLight {
+    /// Isotropic emitter with inverse-square falloff.
+    Point {
+        position: Vec3,
+        intensity: Vec3
+    },
+    /// Point light restricted to a cone, fading out smoothly between `cos_falloff_start` and `cos_total_width`.
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        intensity: Vec3,
+        cos_total_width: f32,
+        cos_falloff_start: f32
+    },
+    /// Parallel light from infinitely far away, like the sun. `irradiance` is measured
+    /// on a surface facing the light.
+    Directional {
+        direction: Vec3,
+        irradiance: Vec3
+    },
+}
+
+impl PunctualLight {
+    pub fn point(position: Vec3, intensity: Vec3) -> Self {
+        PunctualLight::Point {
+            position,
+            intensity
+        }
+    }
+
+    /// Spotlight at `position` aimed at `target`. Angles are half angles of the cone in degrees;
+    /// the light is full strength inside `falloff_start` and zero outside `cone_angle`.
+    pub fn spot(position: Vec3, target: Vec3, intensity: Vec3, cone_angle: f32, falloff_start: f32) -> Self {
+        PunctualLight::Spot {
+            position,
+            direction: unit_vector(target - position),
+            intensity,
+            cos_total_width: cone_angle.to_radians().cos(),
+            cos_falloff_start: falloff_start.min(cone_angle).to_radians().cos()
+        }
+    }
+
+    /// Light travelling along `direction`.
+    pub fn directional(direction: Vec3, irradiance: Vec3) -> Self {
+        PunctualLight::Directional {
+            direction: unit_vector(direction),
+            irradiance
+        }
+    }
+
+    pub fn sample(&self, p: Vec3) -> LightSample {
+        match *self {
+            PunctualLight::Point { position, intensity } => {
+                let d = position - p;
+                let distance = d.length();
+                LightSample {
+                    wi: d / distance,
+                    distance,
+                    radiance: intensity / (distance * distance)
+                }
+            },
+            PunctualLight::Spot { position, direction, intensity, cos_total_width, cos_falloff_start } => {
+                let d = position - p;
+                let distance = d.length();
+                let wi = d / distance;
+                let cos_theta = dot(-wi, direction);
+                let falloff = if cos_theta <= cos_total_width {
+                    0.0
+                }
+                else if cos_theta >= cos_falloff_start {
+                    1.0
+                }
+                else {
+                    let t = (cos_theta - cos_total_width) / (cos_falloff_start - cos_total_width);
+                    t * t * (3.0 - 2.0 * t)
+                };
+                LightSample {
+                    wi,
+                    distance,
+                    radiance: intensity * (falloff / (distance * distance))
+                }
+            },
+            PunctualLight::Directional { direction, irradiance } => {
+                LightSample {
+                    wi: -direction,
+                    distance: f32::MAX,
+                    radiance: irradiance
+                }
+            },
+        }
+    }
+}
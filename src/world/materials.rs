@@ -1,20 +1,101 @@
 extern crate rand;
 use rand::Rng;
 
+use crate::math::onb::Onb;
 use crate::math::ray::Ray;
 use crate::math::vec3::*;
 use super::hitable::HitRecord;
+use super::subsurface::Medium;
+use super::texture::*;
+use super::thin_film::*;
 
+use std::f32;
+use std::sync::Arc;
+
+/// Outcome of sampling a material.
+pub struct ScatterRecord {
+    /// Throughput of the sampled direction: f |cos| / pdf, or the lobe weight for specular lobes.
+    pub attenuation: Vec3,
+    pub scattered: Ray,
+    /// Solid angle density of `scattered`; meaningless when `is_specular` is set.
+    pub pdf: f32,
+    /// Set for delta lobes (mirrors, smooth glass) and for materials that cannot evaluate
+    /// the lobe they sampled. Light sampling cannot reach these, so integrators must follow them.
+    pub is_specular: bool,
+}
+
+impl ScatterRecord {
+    pub fn specular(attenuation: Vec3, scattered: Ray) -> Self {
+        ScatterRecord {
+            attenuation,
+            scattered,
+            pdf: 0.0,
+            is_specular: true
+        }
+    }
+}
+
+/// Directions passed to `eval` and `pdf` are unit vectors pointing away from the surface:
+/// `wo` towards the viewer and `wi` towards the light.
 pub trait Material: Send+Sync {
-    fn scatter(&self, ray_in: &Ray, rec: &HitRecord) -> (bool, Vec3, Ray);
+    /// Picks an incoming direction for light arriving along `ray_in`, or `None` if absorbed.
+    fn sample(&self, ray_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord>;
+
+    /// BSDF value without the cosine term. Zero for purely specular materials.
+    fn eval(&self, _wo: Vec3, _wi: Vec3, _rec: &HitRecord) -> Vec3 {
+        Vec3::zero()
+    }
+
+    /// Solid angle density with which `sample` picks `wi` given `wo`.
+    fn pdf(&self, _wo: Vec3, _wi: Vec3, _rec: &HitRecord) -> f32 {
+        0.0
+    }
+
+    /// Radiance given off towards the origin of `ray_in`.
+    fn emitted(&self, _ray_in: &Ray, _rec: &HitRecord) -> Vec3 {
+        Vec3::zero()
+    }
+
+    /// Whether the surface emits at all, so the scene can add it to its light list.
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    /// Whether `sample` depends on `ray_in.wavelength()`. A spectral path that scatters off
+    /// such a material only carries on for its hero wavelength.
+    fn is_dispersive(&self) -> bool {
+        false
+    }
+
+    /// Medium filling the inside of closed surfaces made of this material. Path tracers enter
+    /// it when a path scatters to below the surface and leave it when one scatters back out.
+    fn medium(&self) -> Option<Medium> {
+        None
+    }
+
+    /// Whether the surface is cut away at this point, so rays carry on through it as if it
+    /// were not there. Every primitive checks it while intersecting, which keeps closest hit
+    /// searches going past the hole and lets shadow rays through.
+    fn cut_out(&self, _u: f32, _v: f32, _p: Vec3) -> bool {
+        false
+    }
+}
+
+/// Normal flipped to the side `wo` is on, for materials that shade both faces alike.
+pub fn facing_normal(wo: Vec3, rec: &HitRecord) -> Vec3 {
+    if dot(wo, rec.normal) < 0.0 { -rec.normal } else { rec.normal }
 }
 
 pub struct Lambertian {
-    albedo: Vec3
+    albedo: Arc<dyn Texture>
 }
 
 impl Lambertian {
     pub fn new(albedo: Vec3) -> Self {
+        Lambertian::with_texture(Arc::new(SolidColor::new(albedo)))
+    }
+
+    pub fn with_texture(albedo: Arc<dyn Texture>) -> Self {
         Lambertian {
             albedo
         }
@@ -22,82 +103,408 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _ray_in: &Ray, rec: &HitRecord) -> (bool, Vec3, Ray) {
-        let target = rec.p + rec.normal + random_in_unit_sphere();
-        let scattered = Ray::new(rec.p, target - rec.p);
-        (true, self.albedo, scattered)
+    fn sample(&self, ray_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let frame = Onb::from_w(facing_normal(-ray_in.direction(), rec));
+        let direction = frame.local(random_cosine_direction());
+        let cosine = dot(direction, frame.w());
+        if cosine <= 0.0 {
+            return None;
+        }
+        // f cos / pdf = (albedo / pi) cos / (cos / pi)
+        Some(ScatterRecord {
+            attenuation: self.albedo.value(rec.u, rec.v, rec.p) * rec.color,
+            scattered: Ray::new(rec.p, direction),
+            pdf: cosine / f32::consts::PI,
+            is_specular: false
+        })
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> Vec3 {
+        if dot(wi, facing_normal(wo, rec)) <= 0.0 {
+            return Vec3::zero();
+        }
+        self.albedo.value(rec.u, rec.v, rec.p) * rec.color / f32::consts::PI
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> f32 {
+        dot(wi, facing_normal(wo, rec)).max(0.0) / f32::consts::PI
     }
 }
 
+/// One-sided area light: emits on the side the normal points to and absorbs everything.
+/// Only spheres and quads join the scene's light list; on other primitives light
+/// sampling integrators see it from the camera and after specular bounces only.
+pub struct DiffuseLight {
+    emit: Arc<dyn Texture>
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Vec3) -> Self {
+        DiffuseLight::with_texture(Arc::new(SolidColor::new(emit)))
+    }
+
+    pub fn with_texture(emit: Arc<dyn Texture>) -> Self {
+        DiffuseLight {
+            emit
+        }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn sample(&self, _ray_in: &Ray, _rec: &HitRecord) -> Option<ScatterRecord> {
+        None
+    }
+
+    fn emitted(&self, ray_in: &Ray, rec: &HitRecord) -> Vec3 {
+        if dot(ray_in.direction(), rec.normal) < 0.0 {
+            self.emit.value(rec.u, rec.v, rec.p)
+        }
+        else {
+            Vec3::zero()
+        }
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+}
+
+/// Mirror-like conductor. `fuzz` jitters the reflected direction by a point in a sphere of
+/// that radius; 0 is a perfect mirror and values are clamped to 1. The fuzzed lobe has no
+/// closed form density, so it is reported as specular; use `RoughConductor` for glossy metal
+/// that light sampling can see.
 pub struct Metal {
-    albedo: Vec3
+    albedo: Arc<dyn Texture>,
+    /// `albedo` when it is the same everywhere, so a coating over it can be tabulated.
+    solid_albedo: Option<Vec3>,
+    fuzz: Arc<dyn Texture>,
+    coating: Option<ThinFilm>,
+    coating_table: Option<FilmTable>
 }
 
 impl Metal {
-    pub fn new(albedo: Vec3) -> Self {
+    pub fn new(albedo: Vec3, fuzz: f32) -> Self {
+        let mut metal = Metal::with_texture(Arc::new(SolidColor::new(albedo)), Arc::new(SolidColor::new(Vec3::new(fuzz, fuzz, fuzz))));
+        metal.solid_albedo = Some(albedo);
+        metal
+    }
+
+    /// Texture driven albedo and roughness; the roughness map is read from its first channel.
+    pub fn with_texture(albedo: Arc<dyn Texture>, fuzz: Arc<dyn Texture>) -> Self {
         Metal {
-            albedo
+            albedo,
+            solid_albedo: None,
+            fuzz,
+            coating: None,
+            coating_table: None
         }
     }
+
+    /// Coats the metal with a thin film, for the tempering colors of heated steel or an oil slick.
+    /// Its colors are worked out from the reflectance spectrum at every scatter when the albedo
+    /// is a texture, and up front otherwise.
+    pub fn with_coating(mut self, film: ThinFilm) -> Self {
+        self.coating = Some(film);
+        self.coating_table = self.solid_albedo.map(|albedo| FilmTable::new(film, Substrate::Metal(albedo)));
+        self
+    }
 }
 
 impl Material for Metal {
-    fn scatter(&self, ray_in: &Ray, rec: &HitRecord) -> (bool, Vec3, Ray) {
+    fn sample(&self, ray_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         let reflected = reflect(unit_vector(ray_in.direction()), rec.normal);
-        let scattered = Ray::new(rec.p, reflected);
-        (dot(scattered.direction(), rec.normal) > 0.0, self.albedo, scattered)
+        let fuzz = self.fuzz.value(rec.u, rec.v, rec.p).x().clamp(0.0, 1.0);
+        let scattered = Ray::new(rec.p, reflected + fuzz * random_in_unit_sphere());
+        if dot(scattered.direction(), rec.normal) > 0.0 {
+            let albedo = self.albedo.value(rec.u, rec.v, rec.p);
+            // The film only tints the reflection, so every wavelength of a spectral path can
+            // share the color of the whole spectrum.
+            let cosine = dot(reflected, rec.normal);
+            let attenuation = match (&self.coating_table, self.coating) {
+                (Some(table), _) => table.color(cosine),
+                (None, Some(film)) => film.color(cosine, Substrate::Metal(albedo)),
+                (None, None) => albedo
+            };
+            Some(ScatterRecord::specular(attenuation, scattered))
+        }
+        else {
+            None
+        }
+    }
+}
+
+/// Index of refraction as a function of wavelength.
+#[derive(Debug, Copy, Clone)]
+pub enum Dispersion {
+    /// n = a + b / lambda^2, with lambda in micrometres.
+    Cauchy { a: f32, b: f32 },
+    /// n^2 = 1 + sum of b_i lambda^2 / (lambda^2 - c_i), with lambda in micrometres.
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+impl Dispersion {
+    /// Schott N-BK7 crown glass, the common optical glass; barely disperses.
+    pub fn bk7() -> Self {
+        Dispersion::Sellmeier {
+            b: [1.039_612, 0.231_792_34, 1.010_469_5],
+            c: [0.006_000_699, 0.020_017_914, 103.560_65]
+        }
+    }
+
+    /// Schott SF11 dense flint glass, which splits white light into strong rainbows.
+    pub fn dense_flint() -> Self {
+        Dispersion::Sellmeier {
+            b: [1.737_597, 0.313_747_35, 1.898_781],
+            c: [0.013_188_707, 0.062_306_814, 155.236_3]
+        }
+    }
+
+    /// Index of refraction at `lambda` nanometres.
+    pub fn ior(&self, lambda: f32) -> f32 {
+        let l2 = (lambda * 1e-3) * (lambda * 1e-3);
+        match *self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let sum: f32 = (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum();
+                (1.0 + sum).sqrt()
+            }
+        }
     }
 }
 
+/// Wavelength of the Fraunhofer d line, where glasses quote their nominal index.
+const D_LINE: f32 = 587.6;
+
 pub struct Dielectric {
-    refraction_index: f32
+    refraction_index: f32,
+    dispersion: Option<Dispersion>,
+    /// Fraction of each channel absorbed per unit distance travelled inside.
+    absorption: Vec3,
+    coating: Option<ThinFilm>,
+    /// The coating's colors at `refraction_index`, for rays without a wavelength.
+    coating_table: Option<FilmTable>
 }
 
 impl Dielectric {
     pub fn new(refraction_index: f32) -> Self {
         Dielectric {
-            refraction_index
+            refraction_index,
+            dispersion: None,
+            absorption: Vec3::zero(),
+            coating: None,
+            coating_table: None
+        }
+    }
+
+    /// Glass whose index follows `dispersion` along rays that carry a wavelength, and is the
+    /// index at the d line along rays that don't.
+    pub fn with_dispersion(dispersion: Dispersion) -> Self {
+        Dielectric {
+            refraction_index: dispersion.ior(D_LINE),
+            dispersion: Some(dispersion),
+            absorption: Vec3::zero(),
+            coating: None,
+            coating_table: None
+        }
+    }
+
+    /// Tints the medium by Beer-Lambert absorption: light that travels a distance `x` inside
+    /// keeps `exp(-absorption * x)` of each channel, so thick parts look darker and more
+    /// saturated. The surface has to be closed for the distances to mean anything.
+    pub fn with_absorption(mut self, absorption: Vec3) -> Self {
+        self.absorption = absorption;
+        self
+    }
+
+    /// Coats the surface with a thin film, e.g. a soap bubble as a film over `Dielectric::new(1.0)`.
+    pub fn with_coating(mut self, film: ThinFilm) -> Self {
+        self.coating = Some(film);
+        self.coating_table = Some(FilmTable::new(film, Substrate::Dielectric(self.refraction_index)));
+        self
+    }
+
+    fn index_along(&self, ray_in: &Ray) -> f32 {
+        match (self.dispersion, ray_in.wavelength()) {
+            (Some(dispersion), Some(lambda)) => dispersion.ior(lambda),
+            _ => self.refraction_index
         }
     }
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, ray_in: &Ray, rec: &HitRecord) -> (bool, Vec3, Ray) {
+    fn sample(&self, ray_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let refraction_index = self.index_along(ray_in);
         let reflected = reflect(ray_in.direction(), rec.normal);
-        let attenuation = Vec3::one();
         let outward_normal;
         let ni_over_nt;
         let d = dot(ray_in.direction(), rec.normal);
+        // Arriving from inside, the ray has just crossed the medium from where it last entered.
+        let attenuation = if d > 0.0 {
+            let distance = rec.t * ray_in.direction().length();
+            Vec3::new(
+                (-self.absorption.x() * distance).exp(),
+                (-self.absorption.y() * distance).exp(),
+                (-self.absorption.z() * distance).exp())
+        }
+        else {
+            Vec3::one()
+        };
         let cosine = {
             if d > 0.0 {
                 outward_normal = -rec.normal;
-                ni_over_nt = self.refraction_index;
-                self.refraction_index * d / ray_in.direction().length()
+                ni_over_nt = refraction_index;
+                refraction_index * d / ray_in.direction().length()
             }
             else {
                 outward_normal = rec.normal;
-                ni_over_nt = 1.0 / self.refraction_index;
+                ni_over_nt = 1.0 / refraction_index;
                 -d / ray_in.direction().length()
             }
         };
         let mut refracted = Vec3::zero();
-        let reflection_probability = {
+        let reflectance = {
             if let Some(r) = refract(ray_in.direction(), outward_normal, ni_over_nt) {
                 refracted = r;
-                shclick(cosine, self.refraction_index)
+                match self.coating {
+                    Some(film) => {
+                        // A film reflects the same from both sides, so take the angle outside.
+                        let cos_outside = if d > 0.0 { dot(unit_vector(r), rec.normal) } else { cosine };
+                        match (&self.coating_table, ray_in.wavelength()) {
+                            (Some(table), None) => table.color(cos_outside),
+                            _ => film.reflectance(ray_in, cos_outside, Substrate::Dielectric(refraction_index))
+                        }
+                    },
+                    None => {
+                        let r = shclick(cosine, refraction_index);
+                        Vec3::new(r, r, r)
+                    }
+                }
             }
             else {
-                1.0
+                Vec3::one()
             }
         };
+        // Pick by the average; the weights make up the difference per channel.
+        let reflection_probability = (reflectance.x() + reflectance.y() + reflectance.z()) / 3.0;
         let mut rng = rand::thread_rng();
         if rng.gen::<f32>() < reflection_probability {
-            let scattered = Ray::new(rec.p, reflected);
-            (true, attenuation, scattered)
+            let weight = reflectance / reflection_probability;
+            Some(ScatterRecord::specular(attenuation * weight, Ray::new(rec.p, reflected)))
         }
         else {
-            let scattered = Ray::new(rec.p, refracted);
-            (true, attenuation, scattered)
+            let weight = (Vec3::one() - reflectance) / (1.0 - reflection_probability);
+            Some(ScatterRecord::specular(attenuation * weight, Ray::new(rec.p, refracted)))
+        }
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.dispersion.is_some() || self.coating.is_some()
+    }
+}
+
+/// Kajiya-Kay style fiber shading for curves. Needs `rec.tangent` along the strand.
+/// Light is split between an omnidirectional diffuse lobe and a specular cone around
+/// the fiber; `diffuse + specular` should stay at or below one per channel.
+/// Sample-only: both lobes are reported as specular.
+pub struct Hair {
+    diffuse: Vec3,
+    specular: Vec3,
+    roughness: f32
+}
+
+impl Hair {
+    pub fn new(diffuse: Vec3, specular: Vec3, roughness: f32) -> Self {
+        Hair {
+            diffuse,
+            specular,
+            roughness
         }
     }
 }
+
+impl Material for Hair {
+    fn sample(&self, ray_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let mut rng = rand::thread_rng();
+        let tangent = rec.tangent;
+        let diffuse_weight = self.diffuse.x() + self.diffuse.y() + self.diffuse.z();
+        let specular_weight = self.specular.x() + self.specular.y() + self.specular.z();
+        if diffuse_weight + specular_weight <= 0.0 {
+            return None;
+        }
+        let specular_probability = specular_weight / (diffuse_weight + specular_weight);
+        let frame = Onb::from_w(tangent);
+        let (n, b) = (frame.u(), frame.v());
+        let phi = 2.0 * f32::consts::PI * rng.gen::<f32>();
+
+        if rng.gen::<f32>() < specular_probability {
+            // Reflection keeps the component along the fiber and spreads around it in azimuth.
+            let d = unit_vector(ray_in.direction());
+            let along = dot(d, tangent);
+            let around = (1.0 - along * along).max(0.0).sqrt();
+            let cone = along * tangent + around * (phi.cos() * n + phi.sin() * b);
+            let direction = unit_vector(cone + self.roughness * random_in_unit_sphere());
+            Some(ScatterRecord::specular(self.specular / specular_probability, Ray::new(rec.p, direction)))
+        }
+        else {
+            // Uniform sphere sampling weighted by the Kajiya-Kay sin(T, L) diffuse term.
+            let z = 1.0 - 2.0 * rng.gen::<f32>();
+            let r = (1.0 - z * z).max(0.0).sqrt();
+            let direction = z * tangent + r * (phi.cos() * n + phi.sin() * b);
+            let sin_tl = r;
+            let weight = 4.0 * sin_tl / f32::consts::PI;
+            Some(ScatterRecord::specular(self.diffuse * (weight / (1.0 - specular_probability)), Ray::new(rec.p, direction)))
+        }
+    }
+}
+
+/// Any material with holes in it where `opacity` drops below `threshold`, for leaves, fences
+/// and lace cut out of a single quad or triangle pair.
+pub struct Cutout {
+    base: Arc<dyn Material>,
+    opacity: Arc<dyn Texture>,
+    threshold: f32
+}
+
+impl Cutout {
+    pub fn new(base: Arc<dyn Material>, opacity: Arc<dyn Texture>, threshold: f32) -> Self {
+        Cutout {
+            base,
+            opacity,
+            threshold
+        }
+    }
+}
+
+impl Material for Cutout {
+    fn sample(&self, ray_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        self.base.sample(ray_in, rec)
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> Vec3 {
+        self.base.eval(wo, wi, rec)
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> f32 {
+        self.base.pdf(wo, wi, rec)
+    }
+
+    fn emitted(&self, ray_in: &Ray, rec: &HitRecord) -> Vec3 {
+        self.base.emitted(ray_in, rec)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.base.is_emissive()
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.base.is_dispersive()
+    }
+
+    fn medium(&self) -> Option<Medium> {
+        self.base.medium()
+    }
+
+    fn cut_out(&self, u: f32, v: f32, p: Vec3) -> bool {
+        self.opacity.scalar(u, v, p) < self.threshold || self.base.cut_out(u, v, p)
+    }
+}
@@ -0,0 +1,533 @@
+extern crate rand;
+use rand::Rng;
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::math::aabb::*;
+use crate::math::ray::Ray;
+use crate::math::vec3::*;
+use super::hitable::*;
+use super::materials::Material;
+use super::texture::Texture;
+
+/// Polygon of a `Mesh`. `uvs` and `normals` index the mesh's uv and normal arrays
+/// per corner and are either empty or as long as `vertices`.
+#[derive(Debug, Clone, Default)]
+pub struct Face {
+    pub vertices: Vec<usize>,
+    pub uvs: Vec<usize>,
+    pub normals: Vec<usize>,
+}
+
+impl Face {
+    /// Corners of the triangles fanning out from the first corner. Faces built by hand with
+    /// fewer than three vertices have none, so they drop out instead of underflowing.
+    pub fn fan(&self) -> impl Iterator<Item = [usize; 3]> {
+        (1..self.vertices.len().max(2) - 1).map(|k| [0, k, k + 1])
+    }
+}
+
+/// Editable polygon mesh, as loaded from an OBJ file. Refine it with
+/// `subdivision::loop_subdivide` and turn it into primitives with `triangles`.
+#[derive(Debug, Clone, Default)]
+pub struct Mesh {
+    pub positions: Vec<Vec3>,
+    pub uvs: Vec<(f32, f32)>,
+    pub normals: Vec<Vec3>,
+    pub faces: Vec<Face>,
+    /// Edges kept sharp by subdivision, stored as (smaller, larger) vertex index.
+    pub creases: HashSet<(usize, usize)>,
+}
+
+pub fn edge_key(a: usize, b: usize) -> (usize, usize) {
+    if a < b { (a, b) } else { (b, a) }
+}
+
+fn parse_index(token: &str, count: usize, line: usize) -> io::Result<usize> {
+    let index: i64 = token.parse().map_err(|_| invalid_data(line, "bad index"))?;
+    let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(invalid_data(line, "index out of range"));
+    }
+    Ok(resolved as usize)
+}
+
+fn parse_floats(tokens: &mut dyn Iterator<Item = &str>, n: usize, line: usize) -> io::Result<Vec<f32>> {
+    let values: Vec<f32> = tokens.take(n).map(|t| t.parse::<f32>()).collect::<Result<_, _>>()
+        .map_err(|_| invalid_data(line, "bad number"))?;
+    if values.len() < n {
+        return Err(invalid_data(line, "too few values"));
+    }
+    Ok(values)
+}
+
+fn invalid_data(line: usize, message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", line, message))
+}
+
+impl Mesh {
+    pub fn new() -> Self {
+        Mesh::default()
+    }
+
+    /// Reads `v`, `vt`, `vn` and `f` statements from a Wavefront OBJ file; everything else is ignored.
+    pub fn load_obj<P: AsRef<Path>>(path: P) -> io::Result<Mesh> {
+        let file = File::open(path)?;
+        Mesh::read_obj(BufReader::new(file))
+    }
+
+    pub fn read_obj<R: BufRead>(reader: R) -> io::Result<Mesh> {
+        let mut mesh = Mesh::new();
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            let number = number + 1;
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("v") => {
+                    let v = parse_floats(&mut tokens, 3, number)?;
+                    mesh.positions.push(Vec3::new(v[0], v[1], v[2]));
+                },
+                Some("vt") => {
+                    let v = parse_floats(&mut tokens, 2, number)?;
+                    mesh.uvs.push((v[0], v[1]));
+                },
+                Some("vn") => {
+                    let v = parse_floats(&mut tokens, 3, number)?;
+                    mesh.normals.push(Vec3::new(v[0], v[1], v[2]));
+                },
+                Some("f") => {
+                    let mut face = Face::default();
+                    for corner in tokens {
+                        let mut parts = corner.split('/');
+                        let v = parts.next().unwrap_or("");
+                        face.vertices.push(parse_index(v, mesh.positions.len(), number)?);
+                        if let Some(vt) = parts.next() {
+                            if !vt.is_empty() {
+                                face.uvs.push(parse_index(vt, mesh.uvs.len(), number)?);
+                            }
+                        }
+                        if let Some(vn) = parts.next() {
+                            if !vn.is_empty() {
+                                face.normals.push(parse_index(vn, mesh.normals.len(), number)?);
+                            }
+                        }
+                    }
+                    if face.vertices.len() < 3 {
+                        return Err(invalid_data(number, "face needs at least three vertices"));
+                    }
+                    if !face.uvs.is_empty() && face.uvs.len() != face.vertices.len() {
+                        face.uvs.clear();
+                    }
+                    if !face.normals.is_empty() && face.normals.len() != face.vertices.len() {
+                        face.normals.clear();
+                    }
+                    mesh.faces.push(face);
+                },
+                _ => {}
+            }
+        }
+        Ok(mesh)
+    }
+
+    pub fn add_crease(&mut self, a: usize, b: usize) {
+        self.creases.insert(edge_key(a, b));
+    }
+
+    /// Marks every edge whose adjacent faces meet at more than `angle_degrees` as a crease.
+    pub fn mark_sharp_edges(&mut self, angle_degrees: f32) {
+        let cos_limit = angle_degrees.to_radians().cos();
+        let face_normals: Vec<Vec3> = self.faces.iter().map(|f| self.face_normal(f)).collect();
+        let mut first_face = std::collections::HashMap::new();
+        for (i, face) in self.faces.iter().enumerate() {
+            let n = face.vertices.len();
+            for k in 0..n {
+                let key = edge_key(face.vertices[k], face.vertices[(k + 1) % n]);
+                if let Some(&other) = first_face.get(&key) {
+                    if dot(face_normals[i], face_normals[other]) < cos_limit {
+                        self.creases.insert(key);
+                    }
+                }
+                else {
+                    first_face.insert(key, i);
+                }
+            }
+        }
+    }
+
+    fn face_normal(&self, face: &Face) -> Vec3 {
+        let mut n = Vec3::zero();
+        for [a, b, c] in face.fan() {
+            let origin = self.positions[face.vertices[a]];
+            n += cross(self.positions[face.vertices[b]] - origin, self.positions[face.vertices[c]] - origin);
+        }
+        let length = n.length();
+        if length > 0.0 { n / length } else { n }
+    }
+
+    /// Area-weighted smooth normal per position.
+    pub fn vertex_normals(&self) -> Vec<Vec3> {
+        let mut normals = vec![Vec3::zero(); self.positions.len()];
+        for face in self.faces.iter() {
+            for corners in face.fan() {
+                let [a, b, c] = corners.map(|k| face.vertices[k]);
+                let n = cross(self.positions[b] - self.positions[a], self.positions[c] - self.positions[a]);
+                normals[a] += n;
+                normals[b] += n;
+                normals[c] += n;
+            }
+        }
+        normals.iter().map(|&n| if n.length() > 0.0 { unit_vector(n) } else { n }).collect()
+    }
+
+    /// Replaces the stored normals with smooth per-vertex normals, e.g. after subdivision.
+    pub fn smooth_normals(&mut self) {
+        self.normals = self.vertex_normals();
+        for face in self.faces.iter_mut() {
+            face.normals = face.vertices.clone();
+        }
+    }
+
+    /// Moves every vertex along its smooth normal by `height(u, v, p)`, sampled at the
+    /// first uv seen for that vertex. Meant to run after subdivision so the detail has vertices to live on.
+    pub fn displace<F: Fn(f32, f32, Vec3) -> f32>(&mut self, height: F) {
+        let normals = self.vertex_normals();
+        let mut vertex_uv: Vec<Option<(f32, f32)>> = vec![None; self.positions.len()];
+        for face in self.faces.iter() {
+            for (k, &v) in face.vertices.iter().enumerate() {
+                if vertex_uv[v].is_none() && !face.uvs.is_empty() {
+                    vertex_uv[v] = Some(self.uvs[face.uvs[k]]);
+                }
+            }
+        }
+        for (i, p) in self.positions.iter_mut().enumerate() {
+            let (u, v) = vertex_uv[i].unwrap_or((0.0, 0.0));
+            *p += height(u, v, *p) * normals[i];
+        }
+        self.smooth_normals();
+    }
+
+    /// `displace` by `scale` times the value of `heights`, the way `NormalMapped::with_bumps`
+    /// fakes it. Subdivide first so there are enough vertices to show the detail.
+    pub fn displace_texture(&mut self, heights: &dyn Texture, scale: f32) {
+        self.displace(|u, v, p| scale * heights.scalar(u, v, p));
+    }
+
+    /// Fan-triangulates every face into primitives sharing one `TriangleMesh`.
+    pub fn triangles(&self, material: Arc<dyn Material>) -> Vec<Arc<dyn Hitable+Send+Sync>> {
+        let mut data = TriangleMesh {
+            positions: self.positions.clone(),
+            indices: vec![],
+            normals: vec![],
+            uvs: vec![],
+            material
+        };
+        let has_normals = self.faces.iter().all(|f| !f.normals.is_empty());
+        let has_uvs = self.faces.iter().all(|f| !f.uvs.is_empty());
+        for face in self.faces.iter() {
+            for corners in face.fan() {
+                data.indices.push(corners.map(|k| face.vertices[k]));
+                if has_normals {
+                    data.normals.push([
+                        self.normals[face.normals[corners[0]]],
+                        self.normals[face.normals[corners[1]]],
+                        self.normals[face.normals[corners[2]]]]);
+                }
+                if has_uvs {
+                    data.uvs.push([
+                        self.uvs[face.uvs[corners[0]]],
+                        self.uvs[face.uvs[corners[1]]],
+                        self.uvs[face.uvs[corners[2]]]]);
+                }
+            }
+        }
+        let data = Arc::new(data);
+        (0..data.indices.len())
+            .map(|index| Arc::new(Triangle { mesh: Arc::clone(&data), index }) as Arc<dyn Hitable+Send+Sync>)
+            .collect()
+    }
+}
+
+/// Render-ready triangle soup shared by all `Triangle`s of a mesh.
+/// `normals` and `uvs` are per corner and either empty or one entry per triangle.
+pub struct TriangleMesh {
+    positions: Vec<Vec3>,
+    indices: Vec<[usize; 3]>,
+    normals: Vec<[Vec3; 3]>,
+    uvs: Vec<[(f32, f32); 3]>,
+    material: Arc<dyn Material>
+}
+
+pub struct Triangle {
+    mesh: Arc<TriangleMesh>,
+    index: usize,
+}
+
+impl Triangle {
+    fn vertices(&self) -> (Vec3, Vec3, Vec3) {
+        let [a, b, c] = self.mesh.indices[self.index];
+        (self.mesh.positions[a], self.mesh.positions[b], self.mesh.positions[c])
+    }
+
+    /// Texture coordinates at barycentrics `b1` and `b2`, which stand in for them without uvs.
+    fn uv(&self, b1: f32, b2: f32) -> (f32, f32) {
+        if self.mesh.uvs.is_empty() {
+            return (b1, b2);
+        }
+        let [(u0, v0), (u1, v1), (u2, v2)] = self.mesh.uvs[self.index];
+        let b0 = 1.0 - b1 - b2;
+        (b0 * u0 + b1 * u1 + b2 * u2, b0 * v0 + b1 * v1 + b2 * v2)
+    }
+
+    /// Möller–Trumbore: ray parameter and the barycentrics of the second and third vertex.
+    /// Misses where the material is cut out.
+    fn intersect(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32, f32)> {
+        let (p0, p1, p2) = self.vertices();
+        let e1 = p1 - p0;
+        let e2 = p2 - p0;
+        let pvec = cross(r.direction(), e2);
+        let det = dot(e1, pvec);
+        if det.abs() < 1e-9 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let tvec = r.origin() - p0;
+        let b1 = dot(tvec, pvec) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+        let qvec = cross(tvec, e1);
+        let b2 = dot(r.direction(), qvec) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+        let t = dot(e2, qvec) * inv_det;
+        if t <= t_min || t >= t_max {
+            return None;
+        }
+        let (u, v) = self.uv(b1, b2);
+        if self.mesh.material.cut_out(u, v, r.point_at_parameter(t)) {
+            return None;
+        }
+        Some((t, b1, b2))
+    }
+
+    fn area(&self) -> f32 {
+        let (p0, p1, p2) = self.vertices();
+        0.5 * cross(p1 - p0, p2 - p0).length()
+    }
+
+    /// Fills in everything but `t` and `p` of a record at barycentrics `b1` and `b2`.
+    fn surface(&self, b1: f32, b2: f32, rec: &mut HitRecord) {
+        let (p0, p1, p2) = self.vertices();
+        let e1 = p1 - p0;
+        let e2 = p2 - p0;
+
+        let b0 = 1.0 - b1 - b2;
+        let geometric_normal = unit_vector(cross(e1, e2));
+        rec.normal = if self.mesh.normals.is_empty() {
+            geometric_normal
+        }
+        else {
+            let [n0, n1, n2] = self.mesh.normals[self.index];
+            unit_vector(b0 * n0 + b1 * n1 + b2 * n2)
+        };
+        rec.geometric_normal = if dot(geometric_normal, rec.normal) < 0.0 { -geometric_normal } else { geometric_normal };
+        let (u, v) = self.uv(b1, b2);
+        rec.u = u;
+        rec.v = v;
+        rec.set_uv_derivatives(e1, e2);
+        if !self.mesh.uvs.is_empty() {
+            let [(u0, v0), (u1, v1), (u2, v2)] = self.mesh.uvs[self.index];
+            let (du1, dv1, du2, dv2) = (u1 - u0, v1 - v0, u2 - u0, v2 - v0);
+            let uv_det = du1 * dv2 - dv1 * du2;
+            if uv_det.abs() > 1e-12 {
+                rec.set_uv_derivatives((dv2 * e1 - dv1 * e2) / uv_det, (du1 * e2 - du2 * e1) / uv_det);
+            }
+            else {
+                rec.set_uv_derivatives(Vec3::zero(), Vec3::zero());
+            }
+        }
+        rec.material = Some(Arc::clone(&self.mesh.material));
+    }
+}
+
+impl Hitable for Triangle {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let (t, b1, b2) = match self.intersect(r, t_min, t_max) {
+            Some(hit) => hit,
+            None => return false
+        };
+        rec.t = t;
+        rec.p = r.point_at_parameter(t);
+        self.surface(b1, b2, rec);
+        true
+    }
+
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.intersect(r, t_min, t_max).is_some()
+    }
+
+    fn bounding_box(&self) -> AabbResult {
+        let (p0, p1, p2) = self.vertices();
+        let pad = 1e-4;
+        let min = Vec3::new(
+            p0.x().min(p1.x()).min(p2.x()) - pad,
+            p0.y().min(p1.y()).min(p2.y()) - pad,
+            p0.z().min(p1.z()).min(p2.z()) - pad);
+        let max = Vec3::new(
+            p0.x().max(p1.x()).max(p2.x()) + pad,
+            p0.y().max(p1.y()).max(p2.y()) + pad,
+            p0.z().max(p1.z()).max(p2.z()) + pad);
+        AabbResult {
+            result: true,
+            aabb: Aabb::new(min, max)
+        }
+    }
+
+    /// Uniform area density converted to solid angle: d^2 / (|cos| A).
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f32 {
+        let t = match self.intersect(&Ray::new(origin, direction), 0.001, f32::MAX) {
+            Some((t, _, _)) => t,
+            None => return 0.0
+        };
+        let (p0, p1, p2) = self.vertices();
+        let normal = unit_vector(cross(p1 - p0, p2 - p0));
+        let distance_squared = t * t * direction.squared_length();
+        let cosine = (dot(direction, normal) / direction.length()).abs();
+        if cosine <= 0.0 {
+            return 0.0;
+        }
+        distance_squared / (cosine * self.area())
+    }
+
+    fn sample_surface(&self) -> Option<(HitRecord, f32)> {
+        let (b1, b2) = uniform_barycentrics();
+        let (p0, p1, p2) = self.vertices();
+        let mut rec = HitRecord::new();
+        rec.p = (1.0 - b1 - b2) * p0 + b1 * p1 + b2 * p2;
+        self.surface(b1, b2, &mut rec);
+        Some((rec, 1.0 / self.area()))
+    }
+
+    fn surface_pdf(&self, _p: Vec3) -> f32 {
+        1.0 / self.area()
+    }
+
+    fn random(&self, origin: Vec3) -> Vec3 {
+        let (b1, b2) = uniform_barycentrics();
+        let (p0, p1, p2) = self.vertices();
+        (1.0 - b1 - b2) * p0 + b1 * p1 + b2 * p2 - origin
+    }
+
+    /// Degenerate triangles have no area to sample.
+    fn is_emissive(&self) -> bool {
+        self.mesh.material.is_emissive() && self.area() > 0.0
+    }
+
+    fn has_medium(&self) -> bool {
+        self.mesh.material.medium().is_some()
+    }
+
+    fn material(&self) -> Option<&Arc<dyn Material>> {
+        Some(&self.mesh.material)
+    }
+}
+
+/// Barycentrics of the second and third vertex, uniformly distributed over the triangle.
+fn uniform_barycentrics() -> (f32, f32) {
+    let mut rng = rand::thread_rng();
+    let root = rng.gen::<f32>().sqrt();
+    let b2 = rng.gen::<f32>() * root;
+    (root - b2, b2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::lights::LightList;
+    use crate::world::materials::{DiffuseLight, Lambertian};
+    use crate::world::subdivision::loop_subdivide;
+    use crate::world::texture::SolidColor;
+
+    const QUAD: &str = "# unit square in the xy plane\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+                        vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nvn 0 0 1\nf 1/1/1 2/2/1 3/3/1 -1/4/1\n";
+
+    #[test]
+    fn reads_obj() {
+        let mesh = Mesh::read_obj(QUAD.as_bytes()).unwrap();
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.faces.len(), 1);
+        assert_eq!(mesh.faces[0].vertices, vec![0, 1, 2, 3]);
+        assert_eq!(mesh.faces[0].uvs, vec![0, 1, 2, 3]);
+        assert_eq!(mesh.faces[0].normals, vec![0, 0, 0, 0]);
+        assert_eq!(mesh.faces[0].fan().collect::<Vec<_>>(), vec![[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn rejects_malformed_obj() {
+        let error = |obj: &str| Mesh::read_obj(obj.as_bytes()).unwrap_err().to_string();
+        assert_eq!(error("v 0 0 0\nv 1 0 0\nf 1 2\n"), "line 3: face needs at least three vertices");
+        assert_eq!(error("v 0 0 0\nf 1 2 3\n"), "line 2: index out of range");
+        assert_eq!(error("v 0 0\n"), "line 1: too few values");
+        assert_eq!(error("v 0 x 0\n"), "line 1: bad number");
+    }
+
+    #[test]
+    fn skips_faces_built_without_three_vertices() {
+        let mut mesh = Mesh::read_obj(QUAD.as_bytes()).unwrap();
+        mesh.faces.push(Face { vertices: vec![], uvs: vec![], normals: vec![] });
+        mesh.faces.push(Face { vertices: vec![0, 1], uvs: vec![], normals: vec![] });
+        assert_eq!(mesh.triangles(Arc::new(Lambertian::new(Vec3::one()))).len(), 2);
+        assert_eq!(loop_subdivide(&mesh, 1).faces.len(), 8);
+    }
+
+    #[test]
+    fn intersects_triangles() {
+        let mesh = Mesh::read_obj(QUAD.as_bytes()).unwrap();
+        let triangles = mesh.triangles(Arc::new(Lambertian::new(Vec3::one())));
+        let ray = Ray::new(Vec3::new(0.75, 0.25, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let mut rec = HitRecord::new();
+        let hits: Vec<_> = triangles.iter().filter(|t| t.hit(&ray, 0.001, f32::MAX, &mut rec)).collect();
+        assert_eq!(hits.len(), 1);
+        assert!((rec.t - 1.0).abs() < 1e-5);
+        assert!((rec.u - 0.75).abs() < 1e-5 && (rec.v - 0.25).abs() < 1e-5);
+        assert!((rec.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-5);
+
+        let miss = Ray::new(Vec3::new(1.5, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(!triangles.iter().any(|t| t.occluded(&miss, 0.001, f32::MAX)));
+    }
+
+    #[test]
+    fn emissive_meshes_are_lights() {
+        let mesh = Mesh::read_obj(QUAD.as_bytes()).unwrap();
+        let lights = LightList::from_hitables(&mesh.triangles(Arc::new(DiffuseLight::new(Vec3::one()))));
+        assert_eq!(lights.len(), 2);
+        for _ in 0..100 {
+            let (rec, pdf) = lights.sample_surface().unwrap();
+            let p = rec.p;
+            assert!(p.z().abs() < 1e-5 && (0.0..=1.0).contains(&p.x()) && (0.0..=1.0).contains(&p.y()));
+            assert!((rec.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-5);
+            assert!((pdf - 1.0).abs() < 1e-4);
+        }
+        // Only the triangle the direction meets contributes, at half the density of the square.
+        let origin = Vec3::new(0.75, 0.25, 2.0);
+        assert!((lights.pdf_value(origin, Vec3::new(0.0, 0.0, -1.0)) - 4.0).abs() < 1e-4);
+        let to_light = lights.random(origin);
+        assert!((origin + to_light).z().abs() < 1e-5);
+    }
+
+    #[test]
+    fn displaces_along_normals_from_a_texture() {
+        let mut mesh = Mesh::read_obj(QUAD.as_bytes()).unwrap();
+        mesh.displace_texture(&SolidColor::new(Vec3::new(0.5, 0.5, 0.5)), 0.2);
+        for p in mesh.positions.iter() {
+            assert!((p.z() - 0.1).abs() < 1e-5);
+        }
+    }
+}
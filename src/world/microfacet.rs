@@ -0,0 +1,339 @@
+extern crate rand;
+use rand::Rng;
+
+use std::f32;
+
+use crate::math::onb::Onb;
+use crate::math::ray::Ray;
+use crate::math::vec3::*;
+use super::hitable::HitRecord;
+use super::materials::*;
+
+/// Isotropic GGX / Trowbridge-Reitz microfacet distribution. Directions are in a local
+/// frame with the macro surface normal along +z.
+#[derive(Debug, Copy, Clone)]
+pub struct Ggx {
+    alpha: f32
+}
+
+impl Ggx {
+    /// `roughness` is the perceptual value in [0, 1]; alpha is its square.
+    pub fn from_roughness(roughness: f32) -> Ggx {
+        let r = roughness.clamp(0.0, 1.0);
+        Ggx {
+            alpha: (r * r).max(1e-3)
+        }
+    }
+
+    pub fn alpha(&self) -> f32 {
+        self.alpha
+    }
+
+    pub fn d(&self, wh: Vec3) -> f32 {
+        let cos2 = wh.z() * wh.z();
+        if cos2 <= 0.0 {
+            return 0.0;
+        }
+        let a2 = self.alpha * self.alpha;
+        let denom = cos2 * (a2 - 1.0) + 1.0;
+        a2 / (f32::consts::PI * denom * denom)
+    }
+
+    pub fn lambda(&self, w: Vec3) -> f32 {
+        let cos2 = w.z() * w.z();
+        if cos2 <= 0.0 {
+            return f32::INFINITY;
+        }
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        0.5 * (-1.0 + (1.0 + self.alpha * self.alpha * tan2).sqrt())
+    }
+
+    pub fn g1(&self, w: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    pub fn g(&self, wo: Vec3, wi: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Density of visible normals: D_wo(wh) = G1(wo) max(0, wo.wh) D(wh) / |wo.z|.
+    pub fn pdf_visible(&self, wo: Vec3, wh: Vec3) -> f32 {
+        let cos_o = wo.z().abs();
+        if cos_o == 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) / cos_o * self.d(wh) * dot(wo, wh).abs()
+    }
+
+    /// Samples a normal from the distribution of normals visible from `wo` (Heitz 2018).
+    /// `wo` may be below the surface; the result always faces +z.
+    pub fn sample_visible(&self, wo: Vec3, u1: f32, u2: f32) -> Vec3 {
+        let wo = if wo.z() < 0.0 { -wo } else { wo };
+        let vh = unit_vector(Vec3::new(self.alpha * wo.x(), self.alpha * wo.y(), wo.z()));
+        let lensq = vh.x() * vh.x() + vh.y() * vh.y();
+        let t1 = if lensq > 0.0 { Vec3::new(-vh.y(), vh.x(), 0.0) / lensq.sqrt() } else { Vec3::new(1.0, 0.0, 0.0) };
+        let t2 = cross(vh, t1);
+        let r = u1.sqrt();
+        let phi = 2.0 * f32::consts::PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z());
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+        let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+        unit_vector(Vec3::new(self.alpha * nh.x(), self.alpha * nh.y(), nh.z().max(1e-6)))
+    }
+}
+
+/// Unpolarized Fresnel reflectance of a dielectric interface. `cos_i` is measured on the side
+/// `eta` (inside over outside) is defined from; negative values mean the ray arrives from inside.
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let (cos_i, eta) = if cos_i < 0.0 { (-cos_i, 1.0 / eta) } else { (cos_i, eta) };
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (r_parallel * r_parallel + r_perpendicular * r_perpendicular)
+}
+
+/// Fresnel reflectance of a conductor with complex index `eta + i k`, per channel.
+pub fn fresnel_conductor(cos_i: f32, eta: Vec3, k: Vec3) -> Vec3 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let cos2 = cos_i * cos_i;
+    let sin2 = 1.0 - cos2;
+    let channel = |i: usize| {
+        let (n, k) = (eta[i], k[i]);
+        let t0 = n * n - k * k - sin2;
+        let a2b2 = (t0 * t0 + 4.0 * n * n * k * k).sqrt();
+        let t1 = a2b2 + cos2;
+        let a = (0.5 * (a2b2 + t0)).max(0.0).sqrt();
+        let t2 = 2.0 * cos_i * a;
+        let rs = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        0.5 * (rp + rs)
+    };
+    Vec3::new(channel(0), channel(1), channel(2))
+}
+
+/// Refracts `wi` (pointing away from the surface) through normal `n`. Returns the transmitted
+/// direction and the relative index actually used, or `None` on total internal reflection.
+pub fn transmit(wi: Vec3, n: Vec3, eta: f32) -> Option<(Vec3, f32)> {
+    let mut cos_i = dot(n, wi);
+    let (mut eta, mut n) = (eta, n);
+    if cos_i < 0.0 {
+        eta = 1.0 / eta;
+        cos_i = -cos_i;
+        n = -n;
+    }
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some((-wi / eta + (cos_i / eta - cos_t) * n, eta))
+}
+
+/// Shading frame with the normal flipped towards `wo`, for one sided reflectors.
+fn facing_frame(wo: Vec3, rec: &HitRecord) -> Onb {
+    Onb::from_w(facing_normal(wo, rec))
+}
+
+/// Rough metal with a GGX distribution and exact conductor Fresnel.
+pub struct RoughConductor {
+    eta: Vec3,
+    k: Vec3,
+    distribution: Ggx
+}
+
+impl RoughConductor {
+    pub fn new(eta: Vec3, k: Vec3, roughness: f32) -> Self {
+        RoughConductor {
+            eta,
+            k,
+            distribution: Ggx::from_roughness(roughness)
+        }
+    }
+
+    pub fn gold(roughness: f32) -> Self {
+        RoughConductor::new(Vec3::new(0.143, 0.374, 1.442), Vec3::new(3.983, 2.385, 1.603), roughness)
+    }
+
+    pub fn copper(roughness: f32) -> Self {
+        RoughConductor::new(Vec3::new(0.200, 0.924, 1.102), Vec3::new(3.912, 2.452, 2.142), roughness)
+    }
+
+    pub fn aluminium(roughness: f32) -> Self {
+        RoughConductor::new(Vec3::new(1.657, 0.880, 0.521), Vec3::new(9.224, 6.270, 4.837), roughness)
+    }
+
+    pub fn silver(roughness: f32) -> Self {
+        RoughConductor::new(Vec3::new(0.155, 0.117, 0.138), Vec3::new(4.828, 3.122, 2.147), roughness)
+    }
+}
+
+impl Material for RoughConductor {
+    fn sample(&self, ray_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let mut rng = rand::thread_rng();
+        let wo_world = -unit_vector(ray_in.direction());
+        let frame = facing_frame(wo_world, rec);
+        let wo = frame.to_local(wo_world);
+        let wh = self.distribution.sample_visible(wo, rng.gen(), rng.gen());
+        let wi = reflect(-wo, wh);
+        if wi.z() <= 0.0 || wo.z() <= 0.0 {
+            return None;
+        }
+        // f cos / pdf collapses to F G / G1 for visible normal sampling.
+        let weight = fresnel_conductor(dot(wo, wh), self.eta, self.k)
+            * (self.distribution.g(wo, wi) / self.distribution.g1(wo));
+        Some(ScatterRecord {
+            attenuation: weight,
+            scattered: Ray::new(rec.p, frame.local(wi)),
+            pdf: self.distribution.pdf_visible(wo, wh) / (4.0 * dot(wo, wh)),
+            is_specular: false
+        })
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> Vec3 {
+        let frame = facing_frame(wo, rec);
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return Vec3::zero();
+        }
+        let wh = unit_vector(wo + wi);
+        fresnel_conductor(dot(wo, wh), self.eta, self.k)
+            * (self.distribution.d(wh) * self.distribution.g(wo, wi) / (4.0 * wo.z() * wi.z()))
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> f32 {
+        let frame = facing_frame(wo, rec);
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return 0.0;
+        }
+        let wh = unit_vector(wo + wi);
+        self.distribution.pdf_visible(wo, wh) / (4.0 * dot(wo, wh))
+    }
+}
+
+/// Frosted glass: GGX reflection and transmission weighted by dielectric Fresnel (Walter et al. 2007).
+/// `rec.normal` must point out of the object, as the sphere and triangle primitives do.
+pub struct RoughDielectric {
+    eta: f32,
+    distribution: Ggx
+}
+
+impl RoughDielectric {
+    pub fn new(refraction_index: f32, roughness: f32) -> Self {
+        RoughDielectric {
+            eta: refraction_index,
+            distribution: Ggx::from_roughness(roughness)
+        }
+    }
+
+    /// `Material::sample` drawing its random numbers from `rng`.
+    pub fn sample_with<R: Rng>(&self, ray_in: &Ray, rec: &HitRecord, rng: &mut R) -> Option<ScatterRecord> {
+        let frame = Onb::from_w(rec.normal);
+        let wo = frame.to_local(-unit_vector(ray_in.direction()));
+        let wh = self.distribution.sample_visible(wo, rng.gen(), rng.gen());
+        let f = fresnel_dielectric(dot(wo, wh), self.eta);
+        let wi = if rng.gen::<f32>() < f {
+            let wi = reflect(-wo, wh);
+            if wo.z() * wi.z() <= 0.0 {
+                return None;
+            }
+            wi
+        }
+        else {
+            match transmit(wo, wh, self.eta) {
+                Some((wi, _)) if wo.z() * wi.z() < 0.0 => wi,
+                _ => return None,
+            }
+        };
+        let pdf = self.pdf_local(wo, wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let weight = self.eval_local(wo, wi) * wi.z().abs() / pdf;
+        Some(ScatterRecord {
+            attenuation: Vec3::new(weight, weight, weight),
+            scattered: Ray::new(rec.p, frame.local(wi)),
+            pdf,
+            is_specular: false
+        })
+    }
+
+    /// Generalized half vector for the pair, facing +z, or `None` for degenerate configurations
+    /// and microfacets seen from behind. Also returns the relative index along the path.
+    fn half_vector(&self, wo: Vec3, wi: Vec3) -> Option<(Vec3, f32)> {
+        let (cos_o, cos_i) = (wo.z(), wi.z());
+        if cos_o == 0.0 || cos_i == 0.0 {
+            return None;
+        }
+        let etap = if cos_o * cos_i > 0.0 { 1.0 } else if cos_o > 0.0 { self.eta } else { 1.0 / self.eta };
+        let wh = etap * wi + wo;
+        if wh.squared_length() == 0.0 {
+            return None;
+        }
+        let wh = unit_vector(wh);
+        let wh = if wh.z() < 0.0 { -wh } else { wh };
+        if dot(wh, wi) * cos_i < 0.0 || dot(wh, wo) * cos_o < 0.0 {
+            return None;
+        }
+        Some((wh, etap))
+    }
+
+    fn eval_local(&self, wo: Vec3, wi: Vec3) -> f32 {
+        let (wh, etap) = match self.half_vector(wo, wi) {
+            Some(h) => h,
+            None => return 0.0,
+        };
+        let f = fresnel_dielectric(dot(wo, wh), self.eta);
+        let d = self.distribution.d(wh);
+        let g = self.distribution.g(wo, wi);
+        if wo.z() * wi.z() > 0.0 {
+            d * g * f / (4.0 * wo.z() * wi.z()).abs()
+        }
+        else {
+            let denom = dot(wi, wh) + dot(wo, wh) / etap;
+            let denom = denom * denom * wi.z() * wo.z();
+            // Radiance is compressed by 1 / etap^2 when it crosses into the denser medium.
+            d * (1.0 - f) * g * (dot(wi, wh) * dot(wo, wh) / denom).abs() / (etap * etap)
+        }
+    }
+
+    fn pdf_local(&self, wo: Vec3, wi: Vec3) -> f32 {
+        let (wh, etap) = match self.half_vector(wo, wi) {
+            Some(h) => h,
+            None => return 0.0,
+        };
+        let f = fresnel_dielectric(dot(wo, wh), self.eta);
+        if wo.z() * wi.z() > 0.0 {
+            self.distribution.pdf_visible(wo, wh) / (4.0 * dot(wo, wh).abs()) * f
+        }
+        else {
+            let denom = dot(wi, wh) + dot(wo, wh) / etap;
+            let dwh_dwi = dot(wi, wh).abs() / (denom * denom);
+            self.distribution.pdf_visible(wo, wh) * dwh_dwi * (1.0 - f)
+        }
+    }
+}
+
+impl Material for RoughDielectric {
+    fn sample(&self, ray_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        self.sample_with(ray_in, rec, &mut rand::thread_rng())
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> Vec3 {
+        let frame = Onb::from_w(rec.normal);
+        let f = self.eval_local(frame.to_local(wo), frame.to_local(wi));
+        Vec3::new(f, f, f)
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> f32 {
+        let frame = Onb::from_w(rec.normal);
+        self.pdf_local(frame.to_local(wo), frame.to_local(wi))
+    }
+}
@@ -0,0 +1,132 @@
+use std::sync::Arc;
+
+use crate::math::ray::Ray;
+use crate::math::vec3::*;
+use super::hitable::HitRecord;
+use super::materials::*;
+use super::subsurface::Medium;
+use super::texture::Texture;
+
+/// Step in uv for the finite differences of bump maps.
+const DELTA: f32 = 1e-3;
+
+/// Where the detail comes from.
+enum Detail {
+    /// Tangent space normals stored as colors, x and y along `tangent` and `bitangent` and z
+    /// along the normal, each mapped from [-1, 1] to [0, 1].
+    Normals(Arc<dyn Texture>),
+    /// Heights along the normal, in world units per unit of texture value times the scale.
+    Heights(Arc<dyn Texture>, f32)
+}
+
+/// Wraps any material and bends the shading normal it sees with a normal or bump map, for
+/// detail too fine to model. The surface itself stays where it is, so silhouettes and shadows
+/// stay smooth; use `Mesh::displace_texture` for that. Needs uvs and uv derivatives on the
+/// surface, which spheres, quads and meshes have.
+pub struct NormalMapped {
+    base: Arc<dyn Material>,
+    detail: Detail
+}
+
+impl NormalMapped {
+    pub fn new(base: Arc<dyn Material>, normals: Arc<dyn Texture>) -> Self {
+        NormalMapped {
+            base,
+            detail: Detail::Normals(normals)
+        }
+    }
+
+    /// Bump map: normals of the surface moved `scale` times the value of `heights` along the
+    /// normal, found by finite differences.
+    pub fn with_bumps(base: Arc<dyn Material>, heights: Arc<dyn Texture>, scale: f32) -> Self {
+        NormalMapped {
+            base,
+            detail: Detail::Heights(heights, scale)
+        }
+    }
+
+    fn normal(&self, rec: &HitRecord) -> Vec3 {
+        let n = rec.normal;
+        match self.detail {
+            Detail::Normals(ref normals) => {
+                let tangent = rec.tangent - dot(rec.tangent, n) * n;
+                if tangent.squared_length() < 1e-12 {
+                    return n;
+                }
+                let tangent = unit_vector(tangent);
+                // Mirrored uvs flip the bitangent.
+                let mut bitangent = cross(n, tangent);
+                if dot(bitangent, rec.bitangent) < 0.0 {
+                    bitangent = -bitangent;
+                }
+                let local = 2.0 * normals.value(rec.u, rec.v, rec.p) - Vec3::one();
+                unit_vector(local.x() * tangent + local.y() * bitangent + local.z() * n)
+            },
+            Detail::Heights(ref heights, scale) => {
+                let height = |u: f32, v: f32, p: Vec3| scale * heights.scalar(u, v, p);
+                let h = height(rec.u, rec.v, rec.p);
+                let dhdu = (height(rec.u + DELTA, rec.v, rec.p + DELTA * rec.dpdu) - h) / DELTA;
+                let dhdv = (height(rec.u, rec.v + DELTA, rec.p + DELTA * rec.dpdv) - h) / DELTA;
+                // Surface gradient of the height, which keeps interpolated normals smooth.
+                let (r1, r2) = (cross(rec.dpdv, n), cross(n, rec.dpdu));
+                let det = dot(rec.dpdu, r1);
+                if det.abs() < 1e-12 {
+                    return n;
+                }
+                unit_vector(n - (dhdu * r1 + dhdv * r2) / det)
+            }
+        }
+    }
+
+    /// `rec` with the mapped normal. Normals that would turn the surface around are ignored,
+    /// since materials tell inside from outside by the normal.
+    fn shade(&self, rec: &HitRecord) -> HitRecord {
+        let mut shaded = rec.clone();
+        let normal = self.normal(rec);
+        if dot(normal, rec.normal) > 0.0 {
+            shaded.normal = normal;
+        }
+        shaded
+    }
+}
+
+impl Material for NormalMapped {
+    fn sample(&self, ray_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        self.base.sample(ray_in, &self.shade(rec))
+    }
+
+    /// Integrators apply the cosine with the normal of `rec`, so this trades it for the one
+    /// with the mapped normal.
+    fn eval(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> Vec3 {
+        let cosine = dot(wi, rec.normal).abs();
+        if cosine < 1e-4 {
+            return Vec3::zero();
+        }
+        let shaded = self.shade(rec);
+        self.base.eval(wo, wi, &shaded) * (dot(wi, shaded.normal).abs() / cosine)
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> f32 {
+        self.base.pdf(wo, wi, &self.shade(rec))
+    }
+
+    fn emitted(&self, ray_in: &Ray, rec: &HitRecord) -> Vec3 {
+        self.base.emitted(ray_in, &self.shade(rec))
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.base.is_emissive()
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.base.is_dispersive()
+    }
+
+    fn medium(&self) -> Option<Medium> {
+        self.base.medium()
+    }
+
+    fn cut_out(&self, u: f32, v: f32, p: Vec3) -> bool {
+        self.base.cut_out(u, v, p)
+    }
+}
@@ -0,0 +1,572 @@
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::math::aabb::*;
+use crate::math::ray::Ray;
+use crate::math::vec3::*;
+use super::hitable::*;
+use super::materials::{Lambertian, Material};
+
+const LEAF_SIZE: usize = 4;
+
+/// One splat. A zero `normal` means the file had none; such points are drawn as discs
+/// facing the incoming ray, which is the only camera-dependent case.
+#[derive(Debug, Copy, Clone)]
+pub struct Point {
+    pub position: Vec3,
+    pub normal: Vec3,
+    pub color: Vec3,
+    pub radius: f32,
+}
+
+impl Point {
+    fn aabb(&self) -> Aabb {
+        let r = self.radius;
+        let n = self.normal;
+        let extent = if n.squared_length() > 0.0 {
+            Vec3::new(
+                r * (1.0 - n.x() * n.x()).max(0.0).sqrt(),
+                r * (1.0 - n.y() * n.y()).max(0.0).sqrt(),
+                r * (1.0 - n.z() * n.z()).max(0.0).sqrt())
+        }
+        else {
+            Vec3::new(r, r, r)
+        };
+        // Keep flat boxes from collapsing to zero thickness.
+        let extent = extent + Vec3::new(1e-4, 1e-4, 1e-4);
+        Aabb::new(self.position - extent, self.position + extent)
+    }
+
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<(f32, Vec3)> {
+        let d = r.direction();
+        let n = if self.normal.squared_length() > 0.0 { self.normal } else { -unit_vector(d) };
+        let denom = dot(d, n);
+        if denom.abs() < 1e-9 {
+            return None;
+        }
+        let t = dot(self.position - r.origin(), n) / denom;
+        if t <= t_min || t >= t_max {
+            return None;
+        }
+        if (r.point_at_parameter(t) - self.position).squared_length() > self.radius * self.radius {
+            return None;
+        }
+        Some((t, if denom < 0.0 { n } else { -n }))
+    }
+}
+
+struct Node {
+    aabb: Aabb,
+    /// Leaf: first point. Interior: index of the right child (the left child follows the node).
+    offset: usize,
+    /// Number of points in a leaf, zero for interior nodes.
+    count: usize,
+}
+
+/// Lidar-style point cloud rendered as oriented discs with per-point color and radius.
+/// Points are kept in a flat BVH of their own, so millions of them cost one entry in the scene `BvhNode`.
+/// All points share one white diffuse material, and their colors reach it through `HitRecord::color`.
+pub struct PointCloud {
+    points: Vec<Point>,
+    nodes: Vec<Node>,
+    material: Arc<dyn Material>,
+}
+
+fn bounds(points: &[Point]) -> Aabb {
+    let mut aabb = points[0].aabb();
+    for p in points.iter().skip(1) {
+        aabb = surrounding_box(aabb, p.aabb());
+    }
+    aabb
+}
+
+fn build(points: &mut [Point], first: usize, nodes: &mut Vec<Node>) -> usize {
+    let index = nodes.len();
+    let aabb = bounds(points);
+    nodes.push(Node { aabb, offset: first, count: points.len() });
+    if points.len() <= LEAF_SIZE {
+        return index;
+    }
+
+    let size = aabb.max() - aabb.min();
+    let axis = if size.x() > size.y() && size.x() > size.z() { 0 } else if size.y() > size.z() { 1 } else { 2 };
+    let mid = points.len() / 2;
+    points.select_nth_unstable_by(mid, |a, b| a.position[axis].partial_cmp(&b.position[axis]).unwrap_or(std::cmp::Ordering::Equal));
+
+    let (left, right) = points.split_at_mut(mid);
+    build(left, first, nodes);
+    let right_index = build(right, first + mid, nodes);
+    nodes[index].offset = right_index;
+    nodes[index].count = 0;
+    index
+}
+
+impl PointCloud {
+    pub fn new(mut points: Vec<Point>) -> Self {
+        let mut nodes = vec![];
+        if !points.is_empty() {
+            build(&mut points, 0, &mut nodes);
+        }
+        PointCloud {
+            points,
+            nodes,
+            material: Arc::new(Lambertian::new(Vec3::one()))
+        }
+    }
+
+    /// Replaces the shared white diffuse material, e.g. with a `Cutout` of it. Point colors
+    /// only show through materials that read `HitRecord::color`.
+    pub fn with_material(mut self, material: Arc<dyn Material>) -> Self {
+        self.material = material;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Loads an ASCII or binary PLY file. Reads `x y z`, and optionally `nx ny nz`,
+    /// `red green blue` and `radius` from the vertex element, which must come first.
+    pub fn load_ply<P: AsRef<Path>>(path: P, default_radius: f32) -> io::Result<PointCloud> {
+        let file = File::open(path)?;
+        Ok(PointCloud::new(read_ply(BufReader::new(file), default_radius)?))
+    }
+
+    /// Loads a whitespace separated text file with `x y z`, `x y z nx ny nz` or
+    /// `x y z nx ny nz r g b` per line. Colors are taken to be 0-255 if any of them is above 1.
+    pub fn load_xyz<P: AsRef<Path>>(path: P, default_radius: f32) -> io::Result<PointCloud> {
+        let file = File::open(path)?;
+        Ok(PointCloud::new(read_xyz(BufReader::new(file), default_radius)?))
+    }
+
+    /// Closest hit as (t, point index, normal), or the first one found when `any_hit` is set.
+    fn intersect(&self, r: &Ray, t_min: f32, t_max: f32, any_hit: bool) -> Option<(f32, usize, Vec3)> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let mut closest = t_max;
+        let mut found: Option<(f32, usize, Vec3)> = None;
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !node.aabb.hit(r, t_min, closest) {
+                continue;
+            }
+            if node.count > 0 {
+                for i in node.offset..node.offset + node.count {
+                    if let Some((t, normal)) = self.points[i].hit(r, t_min, closest) {
+                        if self.material.cut_out(0.0, 0.0, r.point_at_parameter(t)) {
+                            continue;
+                        }
+                        closest = t;
+                        found = Some((t, i, normal));
+                        if any_hit {
+                            return found;
+                        }
+                    }
+                }
+            }
+            else {
+                stack.push(node.offset);
+                stack.push(index + 1);
+            }
+        }
+        found
+    }
+}
+
+impl Hitable for PointCloud {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        if let Some((t, i, normal)) = self.intersect(r, t_min, t_max, false) {
+            rec.t = t;
+            rec.p = r.point_at_parameter(t);
+            rec.normal = normal;
+            rec.geometric_normal = normal;
+            rec.set_uv_derivatives(Vec3::zero(), Vec3::zero());
+            rec.u = 0.0;
+            rec.v = 0.0;
+            rec.color = self.points[i].color;
+            rec.material = Some(Arc::clone(&self.material));
+            return true;
+        }
+        false
+    }
+
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.intersect(r, t_min, t_max, true).is_some()
+    }
+
+    fn bounding_box(&self) -> AabbResult {
+        if self.nodes.is_empty() {
+            return AabbResult {
+                result: false,
+                aabb: Aabb::new(Vec3::zero(), Vec3::zero())
+            };
+        }
+        AabbResult {
+            result: true,
+            aabb: self.nodes[0].aabb
+        }
+    }
+
+    fn has_medium(&self) -> bool {
+        self.material.medium().is_some()
+    }
+
+    fn material(&self) -> Option<&Arc<dyn Material>> {
+        Some(&self.material)
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Colors in files are display values; decode with the gamma 2 curve the film writes.
+fn decode_color(r: f32, g: f32, b: f32) -> Vec3 {
+    Vec3::new(r * r, g * g, b * b)
+}
+
+fn read_xyz<R: BufRead>(reader: R, default_radius: f32) -> io::Result<Vec<Point>> {
+    let mut points = vec![];
+    // Raw colors, which can only be scaled once the whole file has been seen.
+    let mut colors = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let values: Vec<f32> = line.split_whitespace().map(|t| t.parse::<f32>()).collect::<Result<_, _>>()
+            .map_err(|_| invalid_data("bad number in xyz file"))?;
+        if values.len() < 3 {
+            return Err(invalid_data("xyz line needs at least three values"));
+        }
+        let mut point = Point {
+            position: Vec3::new(values[0], values[1], values[2]),
+            normal: Vec3::zero(),
+            color: Vec3::new(0.5, 0.5, 0.5),
+            radius: default_radius,
+        };
+        if values.len() >= 6 {
+            let n = Vec3::new(values[3], values[4], values[5]);
+            if n.squared_length() > 0.0 {
+                point.normal = unit_vector(n);
+            }
+        }
+        if values.len() >= 9 {
+            colors.push((points.len(), Vec3::new(values[6], values[7], values[8])));
+        }
+        points.push(point);
+    }
+    let bytes = colors.iter().any(|(_, c)| c.x() > 1.0 || c.y() > 1.0 || c.z() > 1.0);
+    let scale = if bytes { 1.0 / 255.0 } else { 1.0 };
+    for (i, c) in colors {
+        points[i].color = decode_color(c.x() * scale, c.y() * scale, c.z() * scale);
+    }
+    Ok(points)
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Copy, Clone)]
+enum PlyType {
+    I8, U8, I16, U16, I32, U32, F32, F64,
+}
+
+impl PlyType {
+    fn parse(name: &str) -> io::Result<PlyType> {
+        match name {
+            "char" | "int8" => Ok(PlyType::I8),
+            "uchar" | "uint8" => Ok(PlyType::U8),
+            "short" | "int16" => Ok(PlyType::I16),
+            "ushort" | "uint16" => Ok(PlyType::U16),
+            "int" | "int32" => Ok(PlyType::I32),
+            "uint" | "uint32" => Ok(PlyType::U32),
+            "float" | "float32" => Ok(PlyType::F32),
+            "double" | "float64" => Ok(PlyType::F64),
+            _ => Err(invalid_data("unknown ply property type")),
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            PlyType::I8 | PlyType::U8 => 1,
+            PlyType::I16 | PlyType::U16 => 2,
+            PlyType::I32 | PlyType::U32 | PlyType::F32 => 4,
+            PlyType::F64 => 8,
+        }
+    }
+
+    /// Value that stands for full intensity in a color of this type.
+    fn full_scale(self) -> f32 {
+        match self {
+            PlyType::I8 => i8::MAX as f32,
+            PlyType::U8 => u8::MAX as f32,
+            PlyType::I16 => i16::MAX as f32,
+            PlyType::U16 => u16::MAX as f32,
+            PlyType::I32 => i32::MAX as f32,
+            PlyType::U32 => u32::MAX as f32,
+            PlyType::F32 | PlyType::F64 => 1.0,
+        }
+    }
+
+    fn decode(self, bytes: &[u8], format: PlyFormat) -> f32 {
+        macro_rules! read {
+            ($t:ty, $n:expr) => {{
+                let mut buf = [0u8; $n];
+                buf.copy_from_slice(&bytes[..$n]);
+                if format == PlyFormat::BinaryBigEndian { <$t>::from_be_bytes(buf) } else { <$t>::from_le_bytes(buf) }
+            }};
+        }
+        match self {
+            PlyType::I8 => bytes[0] as i8 as f32,
+            PlyType::U8 => bytes[0] as f32,
+            PlyType::I16 => read!(i16, 2) as f32,
+            PlyType::U16 => read!(u16, 2) as f32,
+            PlyType::I32 => read!(i32, 4) as f32,
+            PlyType::U32 => read!(u32, 4) as f32,
+            PlyType::F32 => read!(f32, 4),
+            PlyType::F64 => read!(f64, 8) as f32,
+        }
+    }
+}
+
+fn read_ply<R: BufRead>(mut reader: R, default_radius: f32) -> io::Result<Vec<Point>> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if line.trim() != "ply" {
+        return Err(invalid_data("missing ply magic"));
+    }
+
+    let mut format = PlyFormat::Ascii;
+    let mut vertex_count = 0;
+    let mut properties: Vec<(String, PlyType)> = vec![];
+    let mut element_index = 0;
+    let mut in_vertex = false;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid_data("unexpected end of ply header"));
+        }
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["format", "ascii", ..] => format = PlyFormat::Ascii,
+            ["format", "binary_little_endian", ..] => format = PlyFormat::BinaryLittleEndian,
+            ["format", "binary_big_endian", ..] => format = PlyFormat::BinaryBigEndian,
+            ["element", name, count] => {
+                in_vertex = *name == "vertex";
+                if in_vertex {
+                    if element_index != 0 {
+                        return Err(invalid_data("ply vertex element must come first"));
+                    }
+                    vertex_count = count.parse().map_err(|_| invalid_data("bad ply vertex count"))?;
+                }
+                element_index += 1;
+            },
+            ["property", "list", ..] if in_vertex => return Err(invalid_data("list properties on ply vertices are not supported")),
+            ["property", kind, name] if in_vertex => properties.push((name.to_string(), PlyType::parse(kind)?)),
+            ["end_header"] => break,
+            _ => {}
+        }
+    }
+
+    let column = |name: &str| properties.iter().position(|(n, _)| n == name);
+    let (x, y, z) = match (column("x"), column("y"), column("z")) {
+        (Some(x), Some(y), Some(z)) => (x, y, z),
+        _ => return Err(invalid_data("ply vertices need x, y and z")),
+    };
+    let normal = match (column("nx"), column("ny"), column("nz")) {
+        (Some(a), Some(b), Some(c)) => Some((a, b, c)),
+        _ => None,
+    };
+    let color = match (column("red"), column("green"), column("blue")) {
+        (Some(a), Some(b), Some(c)) => Some((a, b, c)),
+        _ => None,
+    };
+    let radius = column("radius");
+
+    let make_point = |values: &[f32]| {
+        let mut point = Point {
+            position: Vec3::new(values[x], values[y], values[z]),
+            normal: Vec3::zero(),
+            color: Vec3::new(0.5, 0.5, 0.5),
+            radius: radius.map_or(default_radius, |r| values[r]),
+        };
+        if let Some((a, b, c)) = normal {
+            let n = Vec3::new(values[a], values[b], values[c]);
+            if n.squared_length() > 0.0 {
+                point.normal = unit_vector(n);
+            }
+        }
+        if let Some((a, b, c)) = color {
+            let full = |i: usize| properties[i].1.full_scale();
+            point.color = decode_color(values[a] / full(a), values[b] / full(b), values[c] / full(c));
+        }
+        point
+    };
+
+    let mut points = Vec::with_capacity(vertex_count);
+    let mut values = vec![0.0f32; properties.len()];
+    if format == PlyFormat::Ascii {
+        let mut lines = reader.lines();
+        for _ in 0..vertex_count {
+            let text = lines.next().ok_or_else(|| invalid_data("unexpected end of ply data"))??;
+            let tokens: Vec<&str> = text.split_whitespace().collect();
+            if tokens.len() < values.len() {
+                return Err(invalid_data("short ply vertex line"));
+            }
+            for (value, token) in values.iter_mut().zip(tokens.iter()) {
+                *value = token.parse().map_err(|_| invalid_data("bad number in ply data"))?;
+            }
+            points.push(make_point(&values));
+        }
+    }
+    else {
+        let record_size: usize = properties.iter().map(|(_, t)| t.size()).sum();
+        let mut record = vec![0u8; record_size];
+        for _ in 0..vertex_count {
+            reader.read_exact(&mut record)?;
+            let mut offset = 0;
+            for (value, (_, kind)) in values.iter_mut().zip(properties.iter()) {
+                *value = kind.decode(&record[offset..], format);
+                offset += kind.size();
+            }
+            points.push(make_point(&values));
+        }
+    }
+    Ok(points)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::materials::Cutout;
+    use crate::world::texture::Texture;
+
+    /// Opaque behind z = -1.5 only.
+    struct Far;
+
+    impl Texture for Far {
+        fn value(&self, _u: f32, _v: f32, p: Vec3) -> Vec3 {
+            if p.z() < -1.5 { Vec3::one() } else { Vec3::zero() }
+        }
+    }
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-5
+    }
+
+    #[test]
+    fn reads_ascii_ply() {
+        let ply = "ply\nformat ascii 1.0\nelement vertex 2\nproperty float x\nproperty float y\nproperty float z\n\
+                   property float nx\nproperty float ny\nproperty float nz\nproperty uchar red\nproperty uchar green\n\
+                   property uchar blue\nelement face 0\nproperty list uchar int vertex_indices\nend_header\n\
+                   1 2 3 0 0 2 255 0 51\n-1 0.5 0 0 0 0 0 255 0\n";
+        let points = read_ply(ply.as_bytes(), 0.25).unwrap();
+        assert_eq!(points.len(), 2);
+        assert!(close(points[0].position, Vec3::new(1.0, 2.0, 3.0)));
+        assert!(close(points[0].normal, Vec3::new(0.0, 0.0, 1.0)));
+        assert!(close(points[0].color, Vec3::new(1.0, 0.0, 0.04)));
+        assert_eq!(points[0].radius, 0.25);
+        // A zero normal means there is none.
+        assert!(close(points[1].normal, Vec3::zero()));
+    }
+
+    #[test]
+    fn reads_binary_ply() {
+        let mut ply = b"ply\nformat binary_little_endian 1.0\nelement vertex 1\nproperty float x\nproperty float y\n\
+                        property float z\nproperty ushort red\nproperty ushort green\nproperty ushort blue\n\
+                        property double radius\nend_header\n".to_vec();
+        for v in &[1.0f32, -2.0, 0.5] {
+            ply.extend_from_slice(&v.to_le_bytes());
+        }
+        // Sixteen bit colors are scaled by 65535, not 255.
+        for c in &[65535u16, 0, 32768] {
+            ply.extend_from_slice(&c.to_le_bytes());
+        }
+        ply.extend_from_slice(&0.125f64.to_le_bytes());
+        let points = read_ply(&ply[..], 1.0).unwrap();
+        assert_eq!(points.len(), 1);
+        assert!(close(points[0].position, Vec3::new(1.0, -2.0, 0.5)));
+        let half = 32768.0 / 65535.0;
+        assert!(close(points[0].color, Vec3::new(1.0, 0.0, half * half)));
+        assert_eq!(points[0].radius, 0.125);
+
+        let truncated = &ply[..ply.len() - 4];
+        assert!(read_ply(truncated, 1.0).is_err());
+    }
+
+    #[test]
+    fn reads_xyz() {
+        let xyz = "# x y z nx ny nz r g b\n0 0 0 0 0 0 1 1 0.5\n\n1 2 3 0 2 0 255 0 0\n4 5 6\n";
+        let points = read_xyz(xyz.as_bytes(), 0.5).unwrap();
+        assert_eq!(points.len(), 3);
+        assert!(close(points[0].normal, Vec3::zero()));
+        assert!(close(points[1].normal, Vec3::new(0.0, 1.0, 0.0)));
+        // One color above 1 makes the whole file 0-255.
+        let dim = 1.0 / 255.0;
+        assert!(close(points[0].color, Vec3::new(dim * dim, dim * dim, 0.25 * dim * dim)));
+        assert!(close(points[1].color, Vec3::new(1.0, 0.0, 0.0)));
+        assert!(close(points[2].position, Vec3::new(4.0, 5.0, 6.0)));
+        assert!(close(points[2].color, Vec3::new(0.5, 0.5, 0.5)));
+
+        assert!(read_xyz("1 2\n".as_bytes(), 0.5).is_err());
+        assert!(read_xyz("1 2 x\n".as_bytes(), 0.5).is_err());
+    }
+
+    #[test]
+    fn hits_points_with_their_colors() {
+        let red = Point {
+            position: Vec3::new(0.0, 0.0, -1.0),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            color: Vec3::new(1.0, 0.0, 0.0),
+            radius: 0.1,
+        };
+        let blue = Point {
+            position: Vec3::new(0.0, 0.0, -2.0),
+            color: Vec3::new(0.0, 0.0, 1.0),
+            ..red
+        };
+        let cloud = PointCloud::new(vec![blue, red]);
+        let mut rec = HitRecord::new();
+        assert!(cloud.hit(&Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0)), 0.001, f32::MAX, &mut rec));
+        assert!((rec.t - 1.0).abs() < 1e-5);
+        assert!(close(rec.color, red.color));
+        assert!(!cloud.occluded(&Ray::new(Vec3::zero(), Vec3::new(0.0, 0.2, -1.0)), 0.001, f32::MAX));
+    }
+
+    #[test]
+    fn cut_out_points_let_rays_through() {
+        let near = Point {
+            position: Vec3::new(0.0, 0.0, -1.0),
+            normal: Vec3::zero(),
+            color: Vec3::one(),
+            radius: 0.1,
+        };
+        let far = Point {
+            position: Vec3::new(0.0, 0.0, -2.0),
+            ..near
+        };
+        let material = Arc::new(Cutout::new(Arc::new(Lambertian::new(Vec3::one())), Arc::new(Far), 0.5));
+        let cloud = PointCloud::new(vec![near, far]).with_material(material);
+        let r = Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0));
+        let mut rec = HitRecord::new();
+        assert!(cloud.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!((rec.t - 2.0).abs() < 1e-5);
+        assert!(!cloud.occluded(&r, 0.001, 1.5));
+    }
+}
@@ -0,0 +1,620 @@
+extern crate rand;
+use rand::Rng;
+
+use std::f32;
+use std::sync::Arc;
+use std::sync::OnceLock;
+
+use crate::math::onb::Onb;
+use crate::math::ray::Ray;
+use crate::math::vec3::*;
+use super::hitable::HitRecord;
+use super::materials::*;
+use super::microfacet::*;
+use super::texture::*;
+
+const TABLE_SIZE: usize = 32;
+const TABLE_STRATA: usize = 16;
+
+/// Knobs of the principled BSDF, all in [0, 1] except `ior`. The defaults are a grey plastic.
+#[derive(Debug, Copy, Clone)]
+pub struct PrincipledParams {
+    pub base_color: Vec3,
+    pub metallic: f32,
+    pub roughness: f32,
+    /// Dielectric reflectance; 0.5 gives the common F0 of 0.04.
+    pub specular: f32,
+    pub specular_tint: f32,
+    pub sheen: f32,
+    pub sheen_tint: f32,
+    pub clearcoat: f32,
+    pub clearcoat_roughness: f32,
+    pub transmission: f32,
+    pub ior: f32,
+    /// Fraction of the diffuse light that is scattered through the surface instead of back.
+    pub subsurface: f32,
+}
+
+impl Default for PrincipledParams {
+    fn default() -> Self {
+        PrincipledParams {
+            base_color: Vec3::new(0.8, 0.8, 0.8),
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            specular_tint: 0.0,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.03,
+            transmission: 0.0,
+            ior: 1.5,
+            subsurface: 0.0,
+        }
+    }
+}
+
+fn specular_from_ior(ior: f32) -> f32 {
+    let f0 = ((ior - 1.0) / (ior + 1.0)).powi(2);
+    (f0 / 0.08).clamp(0.0, 1.0)
+}
+
+/// glTF 2.0 metallic-roughness material, including the transmission, ior, clearcoat and
+/// sheen extensions. Defaults follow the glTF specification.
+#[derive(Debug, Copy, Clone)]
+pub struct GltfMaterial {
+    pub base_color_factor: Vec3,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub transmission_factor: f32,
+    pub ior: f32,
+    pub clearcoat_factor: f32,
+    pub clearcoat_roughness_factor: f32,
+    pub sheen_color_factor: Vec3,
+}
+
+impl Default for GltfMaterial {
+    fn default() -> Self {
+        GltfMaterial {
+            base_color_factor: Vec3::one(),
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            transmission_factor: 0.0,
+            ior: 1.5,
+            clearcoat_factor: 0.0,
+            clearcoat_roughness_factor: 0.0,
+            sheen_color_factor: Vec3::zero(),
+        }
+    }
+}
+
+impl From<&GltfMaterial> for PrincipledParams {
+    fn from(m: &GltfMaterial) -> Self {
+        let sheen = m.sheen_color_factor.x().max(m.sheen_color_factor.y()).max(m.sheen_color_factor.z());
+        PrincipledParams {
+            base_color: m.base_color_factor,
+            metallic: m.metallic_factor,
+            roughness: m.roughness_factor,
+            specular: specular_from_ior(m.ior),
+            sheen,
+            sheen_tint: if sheen > 0.0 { 1.0 } else { 0.0 },
+            clearcoat: m.clearcoat_factor,
+            clearcoat_roughness: m.clearcoat_roughness_factor,
+            transmission: m.transmission_factor,
+            ior: m.ior,
+            ..PrincipledParams::default()
+        }
+    }
+}
+
+/// Wavefront MTL statement values. The `P*` entries are the PBR extension and are `None`
+/// when absent, in which case roughness comes from the Phong exponent `Ns`.
+#[derive(Debug, Copy, Clone)]
+pub struct MtlMaterial {
+    pub kd: Vec3,
+    pub ks: Vec3,
+    pub ns: f32,
+    pub ni: f32,
+    /// Dissolve; `Tr` is `1 - d`.
+    pub d: f32,
+    pub pr: Option<f32>,
+    pub pm: Option<f32>,
+    pub ps: Option<f32>,
+    pub pc: Option<f32>,
+    pub pcr: Option<f32>,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        MtlMaterial {
+            kd: Vec3::new(0.8, 0.8, 0.8),
+            ks: Vec3::zero(),
+            ns: 0.0,
+            ni: 1.5,
+            d: 1.0,
+            pr: None,
+            pm: None,
+            ps: None,
+            pc: None,
+            pcr: None,
+        }
+    }
+}
+
+impl From<&MtlMaterial> for PrincipledParams {
+    fn from(m: &MtlMaterial) -> Self {
+        // Blinn-Phong exponent to Beckmann/GGX alpha, then back to perceptual roughness.
+        let phong_roughness = (2.0 / (m.ns.max(0.0) + 2.0)).sqrt().sqrt();
+        let ks = (m.ks.x() + m.ks.y() + m.ks.z()) / 3.0;
+        PrincipledParams {
+            base_color: m.kd,
+            metallic: m.pm.unwrap_or(0.0),
+            roughness: m.pr.unwrap_or(phong_roughness),
+            specular: if m.pm.is_some() || m.pr.is_some() { specular_from_ior(m.ni) } else { (ks / 0.08).clamp(0.0, 1.0) },
+            sheen: m.ps.unwrap_or(0.0),
+            clearcoat: m.pc.unwrap_or(0.0),
+            clearcoat_roughness: m.pcr.unwrap_or(0.03),
+            transmission: (1.0 - m.d).clamp(0.0, 1.0),
+            ior: m.ni,
+            ..PrincipledParams::default()
+        }
+    }
+}
+
+/// Directional albedos used to hand the energy a layer does not reflect to the layers below,
+/// so the stack as a whole never reflects more than arrives.
+struct AlbedoTables {
+    /// E[G2 / G1] over visible normals, indexed by (cos_o, roughness).
+    ggx_g: Vec<f32>,
+    /// E[(1 - wo.h)^5 G2 / G1]; with the above gives Schlick albedo for any F0.
+    ggx_schlick: Vec<f32>,
+    /// Albedo of the unit sheen lobe, indexed by cos_o.
+    sheen: Vec<f32>,
+}
+
+fn table_coordinate(x: f32) -> (usize, usize, f32) {
+    let f = x.clamp(0.0, 1.0) * (TABLE_SIZE - 1) as f32;
+    let i = (f.floor() as usize).min(TABLE_SIZE - 2);
+    (i, i + 1, f - i as f32)
+}
+
+fn table_cos(i: usize) -> f32 {
+    (i as f32 / (TABLE_SIZE - 1) as f32).max(1e-3)
+}
+
+fn build_tables() -> AlbedoTables {
+    let mut ggx_g = vec![0.0; TABLE_SIZE * TABLE_SIZE];
+    let mut ggx_schlick = vec![0.0; TABLE_SIZE * TABLE_SIZE];
+    let mut sheen = vec![0.0; TABLE_SIZE];
+    let strata = TABLE_STRATA * TABLE_STRATA;
+    for i in 0..TABLE_SIZE {
+        let cos_o = table_cos(i);
+        let wo = Vec3::new((1.0 - cos_o * cos_o).sqrt(), 0.0, cos_o);
+        for j in 0..TABLE_SIZE {
+            let distribution = Ggx::from_roughness(j as f32 / (TABLE_SIZE - 1) as f32);
+            let (mut g, mut s) = (0.0, 0.0);
+            for k in 0..strata {
+                let u1 = ((k / TABLE_STRATA) as f32 + 0.5) / TABLE_STRATA as f32;
+                let u2 = ((k % TABLE_STRATA) as f32 + 0.5) / TABLE_STRATA as f32;
+                let wh = distribution.sample_visible(wo, u1, u2);
+                let wi = reflect(-wo, wh);
+                if wi.z() > 0.0 {
+                    let weight = distribution.g(wo, wi) / distribution.g1(wo);
+                    g += weight;
+                    s += weight * (1.0 - dot(wo, wh)).max(0.0).powi(5);
+                }
+            }
+            ggx_g[i * TABLE_SIZE + j] = g / strata as f32;
+            ggx_schlick[i * TABLE_SIZE + j] = s / strata as f32;
+        }
+        let mut a = 0.0;
+        for k in 0..strata {
+            let r1 = ((k / TABLE_STRATA) as f32 + 0.5) / TABLE_STRATA as f32;
+            let r2 = ((k % TABLE_STRATA) as f32 + 0.5) / TABLE_STRATA as f32;
+            let phi = 2.0 * f32::consts::PI * r1;
+            let wi = Vec3::new(r2.sqrt() * phi.cos(), r2.sqrt() * phi.sin(), (1.0 - r2).sqrt());
+            a += (1.0 - dot(wi, unit_vector(wo + wi))).max(0.0).powi(5);
+        }
+        sheen[i] = a / strata as f32;
+    }
+    AlbedoTables {
+        ggx_g,
+        ggx_schlick,
+        sheen
+    }
+}
+
+fn tables() -> &'static AlbedoTables {
+    static TABLES: OnceLock<AlbedoTables> = OnceLock::new();
+    TABLES.get_or_init(build_tables)
+}
+
+/// Directional albedo of a GGX reflector with Schlick Fresnel `f0`.
+fn ggx_albedo(f0: Vec3, cos_o: f32, roughness: f32) -> Vec3 {
+    let t = tables();
+    let (i0, i1, fi) = table_coordinate(cos_o);
+    let (j0, j1, fj) = table_coordinate(roughness);
+    let lookup = |table: &Vec<f32>| {
+        let a = (1.0 - fj) * table[i0 * TABLE_SIZE + j0] + fj * table[i0 * TABLE_SIZE + j1];
+        let b = (1.0 - fj) * table[i1 * TABLE_SIZE + j0] + fj * table[i1 * TABLE_SIZE + j1];
+        (1.0 - fi) * a + fi * b
+    };
+    let (g, s) = (lookup(&t.ggx_g), lookup(&t.ggx_schlick));
+    Vec3::new(
+        f0.x() * g + (1.0 - f0.x()) * s,
+        f0.y() * g + (1.0 - f0.y()) * s,
+        f0.z() * g + (1.0 - f0.z()) * s)
+}
+
+fn sheen_albedo(cos_o: f32) -> f32 {
+    let t = tables();
+    let (i0, i1, f) = table_coordinate(cos_o);
+    (1.0 - f) * t.sheen[i0] + f * t.sheen[i1]
+}
+
+fn schlick(f0: Vec3, cos: f32) -> Vec3 {
+    let s = (1.0 - cos).clamp(0.0, 1.0).powi(5);
+    f0 + s * (Vec3::one() - f0)
+}
+
+fn luminance(c: Vec3) -> f32 {
+    0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
+}
+
+fn average(c: Vec3) -> f32 {
+    (c.x() + c.y() + c.z()) / 3.0
+}
+
+fn lerp(a: Vec3, b: Vec3, t: f32) -> Vec3 {
+    (1.0 - t) * a + t * b
+}
+
+const COAT: usize = 0;
+const METAL: usize = 1;
+const GLASS: usize = 2;
+const SPECULAR: usize = 3;
+const DIFFUSE: usize = 4;
+
+/// Everything about the stack that depends only on the hit and `wo`.
+struct Lobes {
+    frame: Onb,
+    wo: Vec3,
+    base: Vec3,
+    specular_f0: Vec3,
+    specular_albedo: Vec3,
+    sheen_color: Vec3,
+    sheen_albedo: Vec3,
+    scale: [f32; 5],
+    probability: [f32; 5],
+}
+
+/// Disney-style principled BSDF built as a stack that conserves energy by construction:
+/// clearcoat over a metal / glass / plastic mix, the plastic being GGX specular over a
+/// diffuse base with sheen and a thin-surface diffuse transmission standing in for subsurface.
+/// Each layer only receives the energy the layers above it did not reflect.
+pub struct Principled {
+    params: PrincipledParams,
+    base_color: Arc<dyn Texture>,
+    specular: Ggx,
+    coat: Ggx,
+    glass: RoughDielectric,
+}
+
+impl Principled {
+    pub fn new(params: PrincipledParams) -> Self {
+        Principled::with_texture(Arc::new(SolidColor::new(params.base_color)), params)
+    }
+
+    /// Like `new`, with the base color read from `base_color` instead of `params.base_color`.
+    pub fn with_texture(base_color: Arc<dyn Texture>, params: PrincipledParams) -> Self {
+        Principled {
+            params,
+            base_color,
+            specular: Ggx::from_roughness(params.roughness),
+            coat: Ggx::from_roughness(params.clearcoat_roughness),
+            glass: RoughDielectric::new(params.ior, params.roughness),
+        }
+    }
+
+    pub fn params(&self) -> &PrincipledParams {
+        &self.params
+    }
+
+    /// `Material::sample` drawing its random numbers from `rng`.
+    pub fn sample_with<R: Rng>(&self, ray_in: &Ray, rec: &HitRecord, rng: &mut R) -> Option<ScatterRecord> {
+        let wo_world = -unit_vector(ray_in.direction());
+        if self.inside(wo_world, rec) {
+            return self.glass.sample_with(ray_in, rec, rng);
+        }
+
+        let l = self.lobes(wo_world, rec);
+
+        let mut choice: f32 = rng.gen();
+        let mut lobe = DIFFUSE;
+        for (i, q) in l.probability.iter().enumerate() {
+            if choice < *q {
+                lobe = i;
+                break;
+            }
+            choice -= q;
+        }
+        if l.probability[lobe] <= 0.0 {
+            return None;
+        }
+
+        let wi_world = match lobe {
+            COAT => l.frame.local(reflect(-l.wo, self.coat.sample_visible(l.wo, rng.gen(), rng.gen()))),
+            METAL | SPECULAR => l.frame.local(reflect(-l.wo, self.specular.sample_visible(l.wo, rng.gen(), rng.gen()))),
+            GLASS => unit_vector(self.glass.sample_with(ray_in, rec, rng)?.scattered.direction()),
+            _ => {
+                let d = cosine_direction(rng.gen(), rng.gen());
+                let d = if rng.gen::<f32>() < self.translucency() { Vec3::new(d.x(), d.y(), -d.z()) } else { d };
+                l.frame.local(d)
+            }
+        };
+
+        let pdf = self.pdf_lobes(&l, wo_world, wi_world, rec);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let f = self.eval_lobes(&l, wo_world, wi_world, rec);
+        let cos_i = dot(wi_world, l.frame.w()).abs();
+        Some(ScatterRecord {
+            attenuation: f * (cos_i / pdf),
+            scattered: Ray::new(rec.p, wi_world),
+            pdf,
+            is_specular: false
+        })
+    }
+
+
+    /// Light arriving from inside a transmissive object only meets the glass interface.
+    fn inside(&self, wo: Vec3, rec: &HitRecord) -> bool {
+        self.params.transmission > 0.0 && self.params.metallic < 1.0 && dot(wo, rec.normal) < 0.0
+    }
+
+    fn lobes(&self, wo_world: Vec3, rec: &HitRecord) -> Lobes {
+        let p = &self.params;
+        let frame = Onb::from_w(facing_normal(wo_world, rec));
+        let wo = frame.to_local(wo_world);
+        let cos_o = wo.z().max(1e-4);
+        let base = self.base_color.value(rec.u, rec.v, rec.p);
+        let lum = luminance(base);
+        let tint = if lum > 0.0 { base / lum } else { Vec3::one() };
+
+        let specular_f0 = 0.08 * p.specular * lerp(Vec3::one(), tint, p.specular_tint);
+        let specular_albedo = ggx_albedo(specular_f0, cos_o, p.roughness);
+        let sheen_color = p.sheen * lerp(Vec3::one(), tint, p.sheen_tint);
+        let sheen_albedo = sheen_albedo(cos_o) * sheen_color;
+        let coat_albedo = p.clearcoat * average(ggx_albedo(Vec3::new(0.04, 0.04, 0.04), cos_o, p.clearcoat_roughness));
+
+        let under = 1.0 - coat_albedo;
+        let (m, t) = (p.metallic, p.transmission);
+        let scale = [
+            p.clearcoat,
+            under * m,
+            under * (1.0 - m) * t,
+            under * (1.0 - m) * (1.0 - t),
+            under * (1.0 - m) * (1.0 - t),
+        ];
+        let diffuse_energy = average((Vec3::one() - specular_albedo) * (base + sheen_albedo));
+        let mut probability = [
+            coat_albedo,
+            scale[METAL] * average(base).max(0.05),
+            scale[GLASS],
+            scale[SPECULAR] * average(specular_albedo),
+            scale[DIFFUSE] * diffuse_energy,
+        ];
+        let total: f32 = probability.iter().sum();
+        if total > 0.0 {
+            for q in probability.iter_mut() {
+                *q /= total;
+            }
+        }
+
+        Lobes {
+            frame,
+            wo,
+            base,
+            specular_f0,
+            specular_albedo,
+            sheen_color,
+            sheen_albedo,
+            scale,
+            probability,
+        }
+    }
+
+    fn ggx_reflection(distribution: &Ggx, f0: Vec3, wo: Vec3, wi: Vec3) -> Vec3 {
+        if wi.z() <= 0.0 || wo.z() <= 0.0 {
+            return Vec3::zero();
+        }
+        let wh = unit_vector(wo + wi);
+        schlick(f0, dot(wo, wh)) * (distribution.d(wh) * distribution.g(wo, wi) / (4.0 * wo.z() * wi.z()))
+    }
+
+    fn ggx_reflection_pdf(distribution: &Ggx, wo: Vec3, wi: Vec3) -> f32 {
+        if wi.z() <= 0.0 || wo.z() <= 0.0 {
+            return 0.0;
+        }
+        let wh = unit_vector(wo + wi);
+        distribution.pdf_visible(wo, wh) / (4.0 * dot(wo, wh))
+    }
+
+    fn translucency(&self) -> f32 {
+        0.5 * self.params.subsurface
+    }
+
+    fn eval_lobes(&self, l: &Lobes, wo_world: Vec3, wi_world: Vec3, rec: &HitRecord) -> Vec3 {
+        let (wo, wi) = (l.wo, l.frame.to_local(wi_world));
+        let mut f = Vec3::zero();
+        if l.scale[COAT] > 0.0 {
+            f += l.scale[COAT] * Principled::ggx_reflection(&self.coat, Vec3::new(0.04, 0.04, 0.04), wo, wi);
+        }
+        if l.scale[METAL] > 0.0 {
+            f += l.scale[METAL] * Principled::ggx_reflection(&self.specular, l.base, wo, wi);
+        }
+        if l.scale[GLASS] > 0.0 {
+            f += l.scale[GLASS] * self.glass.eval(wo_world, wi_world, rec);
+        }
+        if l.scale[SPECULAR] > 0.0 {
+            f += l.scale[SPECULAR] * Principled::ggx_reflection(&self.specular, l.specular_f0, wo, wi);
+        }
+        if l.scale[DIFFUSE] > 0.0 {
+            let below = Vec3::one() - l.specular_albedo;
+            let q = self.translucency();
+            let diffuse = l.base * (Vec3::one() - l.sheen_albedo);
+            let layer = if wi.z() > 0.0 {
+                let cos_d = dot(wi, unit_vector(wo + wi)).max(0.0);
+                (1.0 - q) * diffuse + (1.0 - cos_d).powi(5) * l.sheen_color
+            }
+            else {
+                q * diffuse
+            };
+            f += l.scale[DIFFUSE] * below * layer / f32::consts::PI;
+        }
+        f
+    }
+
+    fn pdf_lobes(&self, l: &Lobes, wo_world: Vec3, wi_world: Vec3, rec: &HitRecord) -> f32 {
+        let (wo, wi) = (l.wo, l.frame.to_local(wi_world));
+        let mut pdf = 0.0;
+        if l.probability[COAT] > 0.0 {
+            pdf += l.probability[COAT] * Principled::ggx_reflection_pdf(&self.coat, wo, wi);
+        }
+        let specular = l.probability[METAL] + l.probability[SPECULAR];
+        if specular > 0.0 {
+            pdf += specular * Principled::ggx_reflection_pdf(&self.specular, wo, wi);
+        }
+        if l.probability[GLASS] > 0.0 {
+            pdf += l.probability[GLASS] * self.glass.pdf(wo_world, wi_world, rec);
+        }
+        if l.probability[DIFFUSE] > 0.0 {
+            let q = self.translucency();
+            let side = if wi.z() > 0.0 { 1.0 - q } else { q };
+            pdf += l.probability[DIFFUSE] * side * wi.z().abs() / f32::consts::PI;
+        }
+        pdf
+    }
+}
+
+impl Material for Principled {
+    fn sample(&self, ray_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        self.sample_with(ray_in, rec, &mut rand::thread_rng())
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> Vec3 {
+        if self.inside(wo, rec) {
+            return self.glass.eval(wo, wi, rec);
+        }
+        let l = self.lobes(wo, rec);
+        self.eval_lobes(&l, wo, wi, rec)
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> f32 {
+        if self.inside(wo, rec) {
+            return self.glass.pdf(wo, wi, rec);
+        }
+        let l = self.lobes(wo, rec);
+        self.pdf_lobes(&l, wo, wi, rec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /// Average of the sampled throughput under uniform white illumination ("white furnace").
+    /// Seeded, so every run draws the same directions.
+    fn furnace(material: &Principled, cos_o: f32, samples: usize) -> Vec3 {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut rec = HitRecord::new();
+        rec.normal = Vec3::new(0.0, 0.0, 1.0);
+        let wo = Vec3::new((1.0 - cos_o * cos_o).sqrt(), 0.0, cos_o);
+        let ray = Ray::new(wo, -wo);
+        let mut sum = Vec3::zero();
+        for _ in 0..samples {
+            if let Some(srec) = material.sample_with(&ray, &rec, &mut rng) {
+                sum += srec.attenuation;
+            }
+        }
+        sum / samples as f32
+    }
+
+    fn assert_no_energy_gain(params: PrincipledParams) {
+        let material = Principled::new(params);
+        for &cos_o in [1.0, 0.7, 0.3, 0.1].iter() {
+            let albedo = furnace(&material, cos_o, 5000);
+            for i in 0..3 {
+                assert!(albedo[i] <= 1.02, "albedo {:?} at cos {} for {:?}", albedo, cos_o, params);
+            }
+        }
+    }
+
+    fn white(params: PrincipledParams) -> PrincipledParams {
+        PrincipledParams { base_color: Vec3::one(), ..params }
+    }
+
+    #[test]
+    fn diffuse_plastic_conserves_energy() {
+        for &roughness in [0.0, 0.3, 1.0].iter() {
+            assert_no_energy_gain(white(PrincipledParams { roughness, specular: 1.0, ..Default::default() }));
+        }
+    }
+
+    #[test]
+    fn metal_conserves_energy() {
+        for &roughness in [0.05, 0.5, 1.0].iter() {
+            assert_no_energy_gain(white(PrincipledParams { metallic: 1.0, roughness, ..Default::default() }));
+        }
+    }
+
+    #[test]
+    fn clearcoat_and_sheen_conserve_energy() {
+        assert_no_energy_gain(white(PrincipledParams { clearcoat: 1.0, clearcoat_roughness: 0.1, sheen: 1.0, ..Default::default() }));
+        assert_no_energy_gain(white(PrincipledParams { clearcoat: 1.0, metallic: 0.5, roughness: 0.8, ..Default::default() }));
+    }
+
+    #[test]
+    fn transmission_and_subsurface_conserve_energy() {
+        assert_no_energy_gain(white(PrincipledParams { transmission: 1.0, roughness: 0.2, ..Default::default() }));
+        assert_no_energy_gain(white(PrincipledParams { subsurface: 1.0, sheen: 0.5, ..Default::default() }));
+    }
+
+    #[test]
+    fn sampling_matches_eval() {
+        let material = Principled::new(PrincipledParams { clearcoat: 0.5, sheen: 0.3, subsurface: 0.4, ..Default::default() });
+        let mut rec = HitRecord::new();
+        rec.normal = Vec3::new(0.0, 0.0, 1.0);
+        let wo = unit_vector(Vec3::new(0.6, 0.0, 0.8));
+        let sampled = furnace(&material, 0.8, 20000);
+
+        // Integrate f |cos| over the sphere with midpoint quadrature in z and phi instead,
+        // which is uniform in solid angle.
+        let n = 250;
+        let mut integrated = Vec3::zero();
+        for i in 0..n {
+            let z = 1.0 - 2.0 * (i as f32 + 0.5) / n as f32;
+            let r = (1.0 - z * z).sqrt();
+            for j in 0..n {
+                let phi = 2.0 * f32::consts::PI * (j as f32 + 0.5) / n as f32;
+                let wi = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+                integrated += material.eval(wo, wi, &rec) * (z.abs() * 4.0 * f32::consts::PI);
+            }
+        }
+        integrated /= (n * n) as f32;
+        for i in 0..3 {
+            assert!((sampled[i] - integrated[i]).abs() < 0.03, "sampled {:?} integrated {:?}", sampled, integrated);
+        }
+    }
+
+    #[test]
+    fn gltf_defaults_map_to_rough_metal() {
+        let params = PrincipledParams::from(&GltfMaterial::default());
+        assert_eq!(params.metallic, 1.0);
+        assert_eq!(params.roughness, 1.0);
+        assert!((params.specular - 0.5).abs() < 1e-3);
+    }
+}
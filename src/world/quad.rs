@@ -0,0 +1,146 @@
+extern crate rand;
+use rand::Rng;
+
+use std::f32;
+use std::sync::Arc;
+
+use crate::math::aabb::*;
+use crate::math::ray::Ray;
+use crate::math::vec3::*;
+use super::hitable::*;
+use super::materials::Material;
+
+/// Parallelogram spanned by the edges `u` and `v` from the corner `q`.
+/// The normal is `u x v`, which is also the side a `DiffuseLight` shines to.
+pub struct Quad {
+    q: Vec3,
+    u: Vec3,
+    v: Vec3,
+    normal: Vec3,
+    w: Vec3,
+    area: f32,
+    material: Arc<dyn Material>
+}
+
+impl Quad {
+    pub fn new(q: Vec3, u: Vec3, v: Vec3, material: Arc<dyn Material>) -> Self {
+        let n = cross(u, v);
+        Quad {
+            q,
+            u,
+            v,
+            normal: unit_vector(n),
+            w: n / dot(n, n),
+            area: n.length(),
+            material
+        }
+    }
+
+    /// Ray parameter and the hit's coordinates along `u` and `v`, both in [0, 1]. Misses
+    /// where the material is cut out.
+    fn intersect(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32, f32)> {
+        let denominator = dot(self.normal, r.direction());
+        if denominator.abs() < 1e-8 {
+            return None;
+        }
+        let t = dot(self.normal, self.q - r.origin()) / denominator;
+        if t <= t_min || t >= t_max {
+            return None;
+        }
+        let planar = r.point_at_parameter(t) - self.q;
+        let alpha = dot(self.w, cross(planar, self.v));
+        let beta = dot(self.w, cross(self.u, planar));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+        if self.material.cut_out(alpha, beta, r.point_at_parameter(t)) {
+            return None;
+        }
+        Some((t, alpha, beta))
+    }
+}
+
+impl Hitable for Quad {
+    fn hit(&self, r: &Ray, t_min: f32, t_max:f32, rec: &mut HitRecord) -> bool {
+        let (t, alpha, beta) = match self.intersect(r, t_min, t_max) {
+            Some(hit) => hit,
+            None => return false
+        };
+        rec.t = t;
+        rec.p = r.point_at_parameter(t);
+        rec.normal = self.normal;
+        rec.geometric_normal = self.normal;
+        rec.set_uv_derivatives(self.u, self.v);
+        rec.u = alpha;
+        rec.v = beta;
+        rec.material = Some(Arc::clone(&self.material));
+        true
+    }
+
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.intersect(r, t_min, t_max).is_some()
+    }
+
+    fn bounding_box(&self) -> AabbResult {
+        // Pad so axis aligned quads don't get a zero thickness box.
+        let corners = [self.q, self.q + self.u, self.q + self.v, self.q + self.u + self.v];
+        let pad = Vec3::new(1e-4, 1e-4, 1e-4);
+        let mut aabb = Aabb::new(corners[0] - pad, corners[0] + pad);
+        for c in corners.iter() {
+            aabb = surrounding_box(aabb, Aabb::new(*c - pad, *c + pad));
+        }
+        AabbResult {
+            result: true,
+            aabb
+        }
+    }
+
+    /// Uniform area density converted to solid angle: d^2 / (|cos| A).
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f32 {
+        let t = match self.intersect(&Ray::new(origin, direction), 0.001, f32::MAX) {
+            Some((t, _, _)) => t,
+            None => return 0.0
+        };
+        let distance_squared = t * t * direction.squared_length();
+        let cosine = (dot(direction, self.normal) / direction.length()).abs();
+        if cosine <= 0.0 {
+            return 0.0;
+        }
+        distance_squared / (cosine * self.area)
+    }
+
+    fn sample_surface(&self) -> Option<(HitRecord, f32)> {
+        let mut rng = rand::thread_rng();
+        let (alpha, beta) = (rng.gen::<f32>(), rng.gen::<f32>());
+        let mut rec = HitRecord::new();
+        rec.p = self.q + alpha * self.u + beta * self.v;
+        rec.normal = self.normal;
+        rec.geometric_normal = self.normal;
+        rec.set_uv_derivatives(self.u, self.v);
+        rec.u = alpha;
+        rec.v = beta;
+        rec.material = Some(Arc::clone(&self.material));
+        Some((rec, 1.0 / self.area))
+    }
+
+    fn surface_pdf(&self, _p: Vec3) -> f32 {
+        1.0 / self.area
+    }
+
+    fn random(&self, origin: Vec3) -> Vec3 {
+        let mut rng = rand::thread_rng();
+        self.q + rng.gen::<f32>() * self.u + rng.gen::<f32>() * self.v - origin
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    fn has_medium(&self) -> bool {
+        self.material.medium().is_some()
+    }
+
+    fn material(&self) -> Option<&Arc<dyn Material>> {
+        Some(&self.material)
+    }
+}
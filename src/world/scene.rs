@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::f32;
+use std::sync::Arc;
+
+use crate::math::ray::Ray;
+use crate::math::vec3::*;
+use super::bvh::BvhNode;
+use super::camera::Camera;
+use super::hitable::*;
+use super::lights::*;
+use super::materials::Material;
+
+/// What a ray that leaves the scene sees.
+#[derive(Debug, Copy, Clone)]
+pub enum Background {
+    /// White to blue gradient along y.
+    Sky,
+    Solid(Vec3),
+}
+
+impl Background {
+    pub fn value(&self, r: &Ray) -> Vec3 {
+        match *self {
+            Background::Sky => {
+                let unit_direction = unit_vector(r.direction());
+                let t = 0.5 * (unit_direction.y() + 1.0);
+                (1.0 - t) * Vec3::new(1.0, 1.0, 1.0) + t * Vec3::new(0.5, 0.7, 1.0)
+            },
+            Background::Solid(color) => color,
+        }
+    }
+}
+
+/// Everything an integrator needs to shade a ray: geometry, the lights in it, the background
+/// and the camera, which integrators tracing paths from the lights connect to.
+pub struct Scene {
+    pub world: BvhNode,
+    pub lights: LightList,
+    /// Delta lights, which only light sampling integrators can see.
+    pub punctual_lights: Vec<PunctualLight>,
+    pub background: Background,
+    pub camera: Camera,
+    /// Whether any surface is filled with a `Medium`, which not every integrator follows.
+    pub has_media: bool,
+    /// Numbers the materials in the order the scene first uses them, keyed by address.
+    material_ids: HashMap<usize, usize>
+}
+
+impl Scene {
+    pub fn new(mut hitables: Vec<Arc<dyn Hitable+Send+Sync>>, camera: Camera, background: Background) -> Self {
+        let lights = LightList::from_hitables(&hitables);
+        let has_media = hitables.iter().any(|h| h.has_medium());
+        let mut material_ids = HashMap::new();
+        for material in hitables.iter().filter_map(|h| h.material()) {
+            let next = material_ids.len();
+            material_ids.entry(address(material)).or_insert(next);
+        }
+        Scene {
+            world: BvhNode::new(&mut hitables),
+            lights,
+            punctual_lights: vec![],
+            background,
+            camera,
+            has_media,
+            material_ids
+        }
+    }
+
+    pub fn with_light(mut self, light: PunctualLight) -> Self {
+        self.punctual_lights.push(light);
+        self
+    }
+
+    /// Position of `material` among the scene's materials, which unlike its address is the
+    /// same from run to run.
+    pub fn material_id(&self, material: &Arc<dyn Material>) -> Option<usize> {
+        self.material_ids.get(&address(material)).cloned()
+    }
+
+    /// Whether nothing blocks the segment from `p` along the unit direction `wi` up to `distance`.
+    pub fn unoccluded(&self, p: Vec3, wi: Vec3, distance: f32) -> bool {
+        let t_max = if distance < f32::MAX { distance * (1.0 - 1e-4) } else { f32::MAX };
+        !self.world.occluded(&Ray::new(p, wi), 0.001, t_max)
+    }
+}
+
+fn address(material: &Arc<dyn Material>) -> usize {
+    Arc::as_ptr(material) as *const () as usize
+}
@@ -0,0 +1,404 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::math::vec3::*;
+use super::camera::Camera;
+use super::hitable::*;
+use super::layered::*;
+use super::lights::PunctualLight;
+use super::materials::*;
+use super::mesh::Mesh;
+use super::microfacet::*;
+use super::normal_map::NormalMapped;
+use super::quad::Quad;
+use super::scene::*;
+use super::subdivision::loop_subdivide;
+use super::subsurface::*;
+use super::texture::ImageTexture;
+use super::thin_film::ThinFilm;
+
+/// Most `subdivide` levels a mesh may ask for. Each level makes four times the triangles.
+const MAX_SUBDIVISION: u32 = 6;
+
+/// Reads a scene description, resolving mesh files relative to the scene file.
+///
+/// Each line is a statement: a keyword followed by `key=value` parameters in any order.
+/// Vectors are written `x,y,z`, or as one number for all three, and `#` starts a comment:
+///
+/// ```text
+/// camera from=278,278,-800 at=278,278,0 vfov=40
+/// background color=0
+/// material name=red type=lambertian albedo=0.65,0.05,0.05
+/// material name=paint type=coated base=red ior=1.5
+/// sphere center=190,90,190 radius=90 material=paint
+/// ```
+///
+/// The statements are `camera` (`from`, `at`, optional `up`, `vfov`, `aperture`, `focus`),
+/// `background` (`color`, or `sky` for the default gradient), `material` (`name`, `type` and
+/// the type's parameters, see `material`), `sphere` (`center`, `radius`, `material`), `quad`
+/// (`corner`, `u`, `v`, `material`), `mesh` (`file`, an OBJ, and `material`, optionally
+/// `subdivide` levels, at most 6, and a `displacement` image scaled by `displacement_scale=1`) and `light`
+/// (`type` of `point` with `position` and `intensity`, `spot` with `position`, `target`,
+/// `intensity`, `angle` and `falloff`, or `directional` with `direction` and `irradiance`).
+/// Materials must be defined before they are used. Files are relative to the scene file.
+pub fn load_scene<P: AsRef<Path>>(path: P, aspect: f32) -> io::Result<Scene> {
+    let path = path.as_ref();
+    let file = File::open(path)?;
+    read_scene(BufReader::new(file), aspect, path.parent().unwrap_or_else(|| Path::new(".")))
+}
+
+pub fn read_scene<R: BufRead>(reader: R, aspect: f32, directory: &Path) -> io::Result<Scene> {
+    let mut camera = None;
+    let mut background = Background::Sky;
+    let mut materials: HashMap<String, Arc<dyn Material>> = HashMap::new();
+    let mut hitables: Vec<Arc<dyn Hitable+Send+Sync>> = vec![];
+    let mut lights = vec![];
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let number = number + 1;
+        let content = line.split('#').next().unwrap_or("");
+        let mut tokens = content.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue
+        };
+        let statement = Statement::parse(tokens, number)?;
+        match keyword {
+            "camera" => {
+                let from = statement.vector("from")?;
+                let at = statement.vector("at")?;
+                camera = Some(Camera::new(
+                    from,
+                    at,
+                    statement.vector_or("up", Vec3::new(0.0, 1.0, 0.0))?,
+                    statement.float_or("vfov", 40.0)?,
+                    aspect,
+                    statement.float_or("aperture", 0.0)?,
+                    statement.float_or("focus", (at - from).length())?));
+            },
+            "background" => {
+                background = if statement.has("color") { Background::Solid(statement.vector("color")?) } else { Background::Sky };
+            },
+            "material" => {
+                let material = material(&statement, &materials, directory)?;
+                materials.insert(statement.text("name")?.to_string(), material);
+            },
+            "sphere" => {
+                let material = statement.material(&materials)?;
+                hitables.push(Arc::new(Sphere::new(statement.vector("center")?, statement.float("radius")?, material)));
+            },
+            "quad" => {
+                let material = statement.material(&materials)?;
+                hitables.push(Arc::new(Quad::new(statement.vector("corner")?, statement.vector("u")?, statement.vector("v")?, material)));
+            },
+            "mesh" => {
+                let material = statement.material(&materials)?;
+                let mut mesh = Mesh::load_obj(directory.join(statement.text("file")?))?;
+                if statement.has("subdivide") {
+                    let levels = statement.integer("subdivide")?;
+                    if levels > MAX_SUBDIVISION {
+                        return Err(statement.error(&format!("'subdivide' is more than {}", MAX_SUBDIVISION)));
+                    }
+                    mesh = loop_subdivide(&mesh, levels);
+                }
+                if statement.has("displacement") {
+                    let heights = ImageTexture::load_data(directory.join(statement.text("displacement")?))?;
+                    mesh.displace_texture(&heights, statement.float_or("displacement_scale", 1.0)?);
+                }
+                hitables.extend(mesh.triangles(material));
+            },
+            "light" => {
+                lights.push(match statement.text("type")? {
+                    "point" => PunctualLight::point(statement.vector("position")?, statement.vector("intensity")?),
+                    "spot" => PunctualLight::spot(
+                        statement.vector("position")?,
+                        statement.vector("target")?,
+                        statement.vector("intensity")?,
+                        statement.float("angle")?,
+                        statement.float_or("falloff", statement.float("angle")?)?),
+                    "directional" => PunctualLight::directional(statement.vector("direction")?, statement.vector("irradiance")?),
+                    other => return Err(invalid_data(number, &format!("unknown light type '{}'", other)))
+                });
+            },
+            other => return Err(invalid_data(number, &format!("unknown statement '{}'", other)))
+        }
+    }
+    let camera = camera.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "scene has no camera"))?;
+    if hitables.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "scene has no geometry"));
+    }
+    let mut scene = Scene::new(hitables, camera, background);
+    for light in lights {
+        scene = scene.with_light(light);
+    }
+    Ok(scene)
+}
+
+/// Builds a `material` statement. Types and their parameters, with defaults:
+///
+/// - `lambertian`: `albedo`
+/// - `metal`: `albedo`, `fuzz=0`
+/// - `conductor`: GGX, `preset` of gold, copper, aluminium or silver, or `eta` and `k`; `roughness`
+/// - `dielectric`: `ior=1.5`, or `dispersion` of bk7 or dense_flint; `absorption=0`
+/// - `rough_dielectric`: `ior=1.5`, `roughness`
+/// - `light`: `emit`
+/// - `coated`: clearcoat over the material named `base`, `ior=1.5`
+/// - `blend`: materials `a` and `b`, taking `b` with probability `weight=0.5`
+/// - `subsurface`: `ior=1.4`, `albedo`, mean free path `mfp`, anisotropy `g=0`
+/// - `normal_map`: the material named `base` with the tangent space normals in image `map`
+/// - `bump`: the material named `base` bumped by `scale=1` times the heights in image `map`
+/// - `cutout`: the material named `base` with holes where the opacity in image `map`, its
+///   alpha channel if it has one, is below `threshold=0.5`
+///
+/// Metals and dielectrics take a thin film coating with `film_thickness` in nanometres and
+/// `film_ior=1.33`.
+fn material(statement: &Statement, materials: &HashMap<String, Arc<dyn Material>>, directory: &Path) -> io::Result<Arc<dyn Material>> {
+    let film = if statement.has("film_thickness") {
+        Some(ThinFilm::new(statement.float("film_thickness")?, statement.float_or("film_ior", 1.33)?))
+    }
+    else {
+        None
+    };
+    let named = |key: &str| -> io::Result<Arc<dyn Material>> {
+        let name = statement.text(key)?;
+        materials.get(name).cloned().ok_or_else(|| statement.error(&format!("unknown material '{}'", name)))
+    };
+    Ok(match statement.text("type")? {
+        "lambertian" => Arc::new(Lambertian::new(statement.vector("albedo")?)),
+        "metal" => {
+            let metal = Metal::new(statement.vector("albedo")?, statement.float_or("fuzz", 0.0)?);
+            Arc::new(match film { Some(film) => metal.with_coating(film), None => metal })
+        },
+        "conductor" => {
+            let roughness = statement.float("roughness")?;
+            Arc::new(match statement.text("preset") {
+                Ok("gold") => RoughConductor::gold(roughness),
+                Ok("copper") => RoughConductor::copper(roughness),
+                Ok("aluminium") => RoughConductor::aluminium(roughness),
+                Ok("silver") => RoughConductor::silver(roughness),
+                Ok(other) => return Err(statement.error(&format!("unknown conductor '{}'", other))),
+                Err(_) => RoughConductor::new(statement.vector("eta")?, statement.vector("k")?, roughness)
+            })
+        },
+        "dielectric" => {
+            let glass = match statement.text("dispersion") {
+                Ok("bk7") => Dielectric::with_dispersion(Dispersion::bk7()),
+                Ok("dense_flint") => Dielectric::with_dispersion(Dispersion::dense_flint()),
+                Ok(other) => return Err(statement.error(&format!("unknown dispersion '{}'", other))),
+                Err(_) => Dielectric::new(statement.float_or("ior", 1.5)?)
+            };
+            let glass = glass.with_absorption(statement.vector_or("absorption", Vec3::zero())?);
+            Arc::new(match film { Some(film) => glass.with_coating(film), None => glass })
+        },
+        "rough_dielectric" => Arc::new(RoughDielectric::new(statement.float_or("ior", 1.5)?, statement.float("roughness")?)),
+        "light" => Arc::new(DiffuseLight::new(statement.vector("emit")?)),
+        "coated" => Arc::new(Coated::new(named("base")?, statement.float_or("ior", 1.5)?)),
+        "blend" => Arc::new(Blend::new(named("a")?, named("b")?, statement.float_or("weight", 0.5)?)),
+        "subsurface" => {
+            let medium = Medium::from_albedo(statement.vector("albedo")?, statement.vector("mfp")?)
+                .with_anisotropy(statement.float_or("g", 0.0)?);
+            Arc::new(Subsurface::new(statement.float_or("ior", 1.4)?, medium))
+        },
+        "normal_map" => {
+            let normals = ImageTexture::load_data(directory.join(statement.text("map")?))?;
+            Arc::new(NormalMapped::new(named("base")?, Arc::new(normals)))
+        },
+        "bump" => {
+            let heights = ImageTexture::load_data(directory.join(statement.text("map")?))?;
+            Arc::new(NormalMapped::with_bumps(named("base")?, Arc::new(heights), statement.float_or("scale", 1.0)?))
+        },
+        "cutout" => {
+            let opacity = ImageTexture::load_alpha(directory.join(statement.text("map")?))?;
+            Arc::new(Cutout::new(named("base")?, Arc::new(opacity), statement.float_or("threshold", 0.5)?))
+        },
+        other => return Err(statement.error(&format!("unknown material type '{}'", other)))
+    })
+}
+
+/// The `key=value` parameters of one line.
+struct Statement<'a> {
+    line: usize,
+    params: HashMap<&'a str, &'a str>,
+}
+
+impl<'a> Statement<'a> {
+    fn parse<I: Iterator<Item = &'a str>>(tokens: I, line: usize) -> io::Result<Self> {
+        let mut params = HashMap::new();
+        for token in tokens {
+            let mut parts = token.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => {
+                    params.insert(key, value);
+                },
+                // Bare words are flags, like `background sky`.
+                (Some(key), None) => {
+                    params.insert(key, "");
+                },
+                _ => {}
+            }
+        }
+        Ok(Statement {
+            line,
+            params
+        })
+    }
+
+    fn error(&self, message: &str) -> io::Error {
+        invalid_data(self.line, message)
+    }
+
+    fn has(&self, key: &str) -> bool {
+        self.params.contains_key(key)
+    }
+
+    fn text(&self, key: &str) -> io::Result<&'a str> {
+        self.params.get(key).cloned().ok_or_else(|| self.error(&format!("missing '{}'", key)))
+    }
+
+    fn float(&self, key: &str) -> io::Result<f32> {
+        self.text(key)?.parse().map_err(|_| self.error(&format!("'{}' is not a number", key)))
+    }
+
+    fn integer(&self, key: &str) -> io::Result<u32> {
+        self.text(key)?.parse().map_err(|_| self.error(&format!("'{}' is not a whole number", key)))
+    }
+
+    fn float_or(&self, key: &str, default: f32) -> io::Result<f32> {
+        if self.has(key) { self.float(key) } else { Ok(default) }
+    }
+
+    fn vector(&self, key: &str) -> io::Result<Vec3> {
+        let values: Vec<f32> = self.text(key)?.split(',').map(|v| v.parse::<f32>()).collect::<Result<_, _>>()
+            .map_err(|_| self.error(&format!("'{}' is not a vector", key)))?;
+        match values.len() {
+            1 => Ok(Vec3::new(values[0], values[0], values[0])),
+            3 => Ok(Vec3::new(values[0], values[1], values[2])),
+            _ => Err(self.error(&format!("'{}' needs one or three values", key)))
+        }
+    }
+
+    fn vector_or(&self, key: &str, default: Vec3) -> io::Result<Vec3> {
+        if self.has(key) { self.vector(key) } else { Ok(default) }
+    }
+
+    fn material(&self, materials: &HashMap<String, Arc<dyn Material>>) -> io::Result<Arc<dyn Material>> {
+        let name = self.text("material")?;
+        materials.get(name).cloned().ok_or_else(|| self.error(&format!("unknown material '{}'", name)))
+    }
+}
+
+fn invalid_data(line: usize, message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", line, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::ray::Ray;
+
+    fn read(text: &str) -> io::Result<Scene> {
+        read_scene(text.as_bytes(), 2.0, Path::new("."))
+    }
+
+    fn error(text: &str) -> String {
+        match read(text) {
+            Ok(_) => panic!("read a broken scene:\n{}", text),
+            Err(e) => {
+                assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+                e.to_string()
+            }
+        }
+    }
+
+    const SCENE: &str = "
+        # Two balls on a floor under a lamp.
+        camera from=0,1,5 at=0,0.5,0 vfov=30
+        background color=0.1
+
+        material name=clay type=lambertian albedo=0.7,0.5,0.4
+        material name=steel type=metal albedo=0.8 fuzz=0.1
+        material name=flakes type=blend a=clay b=steel weight=0.3
+        material name=paint type=coated base=flakes ior=1.5
+        material name=glass type=dielectric dispersion=bk7 absorption=0.1,0,0 film_thickness=300
+        material name=lamp type=light emit=10
+
+        sphere center=-1,0.5,0 radius=0.5 material=paint
+        sphere center=1,0.5,0 radius=0.5 material=glass   # trailing comment
+        quad corner=-5,0,-5 u=0,0,10 v=10,0,0 material=clay
+        quad corner=-0.5,3,-0.5 u=1,0,0 v=0,0,1 material=lamp
+        light type=point position=0,4,2 intensity=5
+        light type=spot position=0,4,0 target=0,0,0 intensity=20 angle=30
+    ";
+
+    #[test]
+    fn reads_a_scene() {
+        let scene = read(SCENE).unwrap();
+        assert_eq!(scene.lights.len(), 1);
+        assert_eq!(scene.punctual_lights.len(), 2);
+        match scene.background {
+            Background::Solid(color) => assert_eq!(color.x(), 0.1),
+            Background::Sky => panic!("background not read")
+        }
+        let mut rec = HitRecord::new();
+        let ray = Ray::new(Vec3::new(-1.0, 0.5, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(scene.world.hit(&ray, 0.001, f32::MAX, &mut rec));
+        assert!((rec.t - 4.5).abs() < 1e-3);
+        let down = Ray::new(Vec3::new(3.0, 1.0, 3.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!(scene.world.hit(&down, 0.001, f32::MAX, &mut rec));
+        assert!((rec.t - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn rejects_malformed_scenes() {
+        let camera = "camera from=0,0,5 at=0,0,0\n";
+        let cases = [
+            (format!("{}teapot size=1", camera), "line 2: unknown statement 'teapot'"),
+            (format!("{}material name=a type=velvet", camera), "line 2: unknown material type 'velvet'"),
+            (format!("{}material name=a type=lambertian", camera), "line 2: missing 'albedo'"),
+            (format!("{}material name=a type=lambertian albedo=1,2", camera), "line 2: 'albedo' needs one or three values"),
+            (format!("{}material name=a type=lambertian albedo=red", camera), "line 2: 'albedo' is not a vector"),
+            (format!("{}material name=a type=metal albedo=1 fuzz=lots", camera), "line 2: 'fuzz' is not a number"),
+            (format!("{}material name=a type=coated base=missing", camera), "line 2: unknown material 'missing'"),
+            (format!("{}\nsphere center=0 radius=1 material=nothing", camera), "line 3: unknown material 'nothing'"),
+            (format!("{}light type=area", camera), "line 2: unknown light type 'area'"),
+            ("sphere center=0 radius=1 material=a".to_string(), "line 1: unknown material 'a'"),
+            ("background sky".to_string(), "scene has no camera"),
+            (format!("{}light type=point position=0 intensity=1", camera), "scene has no geometry"),
+        ];
+        for (text, message) in cases.iter() {
+            assert_eq!(error(text), *message, "for:\n{}", text);
+        }
+    }
+
+    #[test]
+    fn loads_meshes_next_to_the_scene_file() {
+        let dir = std::env::temp_dir().join(format!("scene_file_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("tri.obj"), "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        let good = "camera from=0,0,5 at=0,0,0\nmaterial name=a type=lambertian albedo=1\nmesh file=tri.obj material=a subdivide=1\n";
+        std::fs::write(dir.join("good.scene"), good).unwrap();
+        assert!(load_scene(dir.join("good.scene"), 1.0).is_ok());
+        let bad = good.replace("subdivide=1", "subdivide=1.5");
+        std::fs::write(dir.join("bad.scene"), bad).unwrap();
+        let e = load_scene(dir.join("bad.scene"), 1.0).err().unwrap();
+        assert_eq!(e.to_string(), "line 3: 'subdivide' is not a whole number");
+        let deep = good.replace("subdivide=1", "subdivide=20");
+        std::fs::write(dir.join("deep.scene"), deep).unwrap();
+        let e = load_scene(dir.join("deep.scene"), 1.0).err().unwrap();
+        assert_eq!(e.to_string(), "line 3: 'subdivide' is more than 6");
+        // Meshes without faces leave nothing to render.
+        std::fs::write(dir.join("points.obj"), "v 0 0 0
+v 1 0 0
+v 0 1 0
+").unwrap();
+        std::fs::write(dir.join("empty.scene"), good.replace("tri.obj", "points.obj")).unwrap();
+        let e = load_scene(dir.join("empty.scene"), 1.0).err().unwrap();
+        assert_eq!(e.to_string(), "scene has no geometry");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use crate::math::vec3::Vec3;
+use super::mesh::*;
+
+struct Edge {
+    midpoint: usize,
+    opposite: Vec<usize>,
+}
+
+/// Refines `mesh` `levels` times with Loop subdivision. Polygons are fan-triangulated first.
+/// Boundary edges and edges in `mesh.creases` use the crease rules and stay sharp at every
+/// level; vertices with more than two sharp edges are treated as corners and do not move.
+/// UVs are interpolated linearly per face corner and smooth normals are recomputed.
+pub fn loop_subdivide(mesh: &Mesh, levels: u32) -> Mesh {
+    let mut current = triangulate(mesh);
+    for _ in 0..levels {
+        current = subdivide_once(&current);
+    }
+    current.smooth_normals();
+    current
+}
+
+fn triangulate(mesh: &Mesh) -> Mesh {
+    let mut result = Mesh {
+        positions: mesh.positions.clone(),
+        uvs: mesh.uvs.clone(),
+        normals: vec![],
+        faces: vec![],
+        creases: mesh.creases.clone(),
+    };
+    for face in mesh.faces.iter() {
+        for corners in face.fan() {
+            result.faces.push(Face {
+                vertices: corners.iter().map(|&c| face.vertices[c]).collect(),
+                uvs: if face.uvs.is_empty() { vec![] } else { corners.iter().map(|&c| face.uvs[c]).collect() },
+                normals: vec![],
+            });
+        }
+    }
+    result
+}
+
+fn subdivide_once(mesh: &Mesh) -> Mesh {
+    let vertex_count = mesh.positions.len();
+
+    let mut edges: HashMap<(usize, usize), Edge> = HashMap::new();
+    let mut neighbours: Vec<HashSet<usize>> = vec![HashSet::new(); vertex_count];
+    for face in mesh.faces.iter() {
+        for k in 0..3 {
+            let a = face.vertices[k];
+            let b = face.vertices[(k + 1) % 3];
+            let c = face.vertices[(k + 2) % 3];
+            let next = vertex_count + edges.len();
+            edges.entry(edge_key(a, b))
+                .or_insert(Edge { midpoint: next, opposite: vec![] })
+                .opposite.push(c);
+            neighbours[a].insert(b);
+            neighbours[b].insert(a);
+        }
+    }
+
+    let is_sharp = |key: &(usize, usize), edge: &Edge| edge.opposite.len() != 2 || mesh.creases.contains(key);
+
+    let mut sharp_neighbours: Vec<Vec<usize>> = vec![vec![]; vertex_count];
+    for (key, edge) in edges.iter() {
+        if is_sharp(key, edge) {
+            sharp_neighbours[key.0].push(key.1);
+            sharp_neighbours[key.1].push(key.0);
+        }
+    }
+
+    let mut positions = vec![Vec3::zero(); vertex_count + edges.len()];
+    for v in 0..vertex_count {
+        let p = mesh.positions[v];
+        positions[v] = match sharp_neighbours[v].len() {
+            0 | 1 => {
+                let n = neighbours[v].len();
+                if n == 0 {
+                    p
+                }
+                else {
+                    let beta = if n == 3 { 3.0 / 16.0 } else { 3.0 / (8.0 * n as f32) };
+                    let mut sum = Vec3::zero();
+                    for &u in neighbours[v].iter() {
+                        sum += mesh.positions[u];
+                    }
+                    (1.0 - n as f32 * beta) * p + beta * sum
+                }
+            },
+            2 => {
+                let a = mesh.positions[sharp_neighbours[v][0]];
+                let b = mesh.positions[sharp_neighbours[v][1]];
+                0.75 * p + 0.125 * (a + b)
+            },
+            _ => p,
+        };
+    }
+
+    let mut creases = HashSet::new();
+    for (key, edge) in edges.iter() {
+        let a = mesh.positions[key.0];
+        let b = mesh.positions[key.1];
+        positions[edge.midpoint] = if is_sharp(key, edge) {
+            0.5 * (a + b)
+        }
+        else {
+            let c = mesh.positions[edge.opposite[0]];
+            let d = mesh.positions[edge.opposite[1]];
+            0.375 * (a + b) + 0.125 * (c + d)
+        };
+        if mesh.creases.contains(key) {
+            creases.insert(edge_key(key.0, edge.midpoint));
+            creases.insert(edge_key(edge.midpoint, key.1));
+        }
+    }
+
+    let mut uvs = mesh.uvs.clone();
+    let mut faces = Vec::with_capacity(mesh.faces.len() * 4);
+    for face in mesh.faces.iter() {
+        let v = &face.vertices;
+        let m: Vec<usize> = (0..3).map(|k| edges[&edge_key(v[k], v[(k + 1) % 3])].midpoint).collect();
+
+        // Face-varying uvs: each triangle gets its own midpoint uvs so seams stay intact.
+        let t: Vec<usize> = if face.uvs.is_empty() {
+            vec![]
+        }
+        else {
+            let mut t = face.uvs.clone();
+            for k in 0..3 {
+                let (u0, v0) = mesh.uvs[face.uvs[k]];
+                let (u1, v1) = mesh.uvs[face.uvs[(k + 1) % 3]];
+                uvs.push((0.5 * (u0 + u1), 0.5 * (v0 + v1)));
+                t.push(uvs.len() - 1);
+            }
+            t
+        };
+
+        let triangles = [[0, 3, 5], [3, 1, 4], [5, 4, 2], [3, 4, 5]];
+        let all = [v[0], v[1], v[2], m[0], m[1], m[2]];
+        for tri in triangles.iter() {
+            faces.push(Face {
+                vertices: tri.iter().map(|&i| all[i]).collect(),
+                uvs: if t.is_empty() { vec![] } else { tri.iter().map(|&i| t[i]).collect() },
+                normals: vec![],
+            });
+        }
+    }
+
+    Mesh {
+        positions,
+        uvs,
+        normals: vec![],
+        faces,
+        creases,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-5
+    }
+
+    fn mesh(positions: Vec<Vec3>, faces: &[&[usize]]) -> Mesh {
+        Mesh {
+            positions,
+            faces: faces.iter().map(|f| Face { vertices: f.to_vec(), uvs: vec![], normals: vec![] }).collect(),
+            ..Mesh::default()
+        }
+    }
+
+    /// 3x3 vertices over [0, 2]^2 as four quads, with heights `z(x, y)`.
+    fn grid(z: fn(f32, f32) -> f32) -> Mesh {
+        let positions = (0..9).map(|i| {
+            let (x, y) = ((i % 3) as f32, (i / 3) as f32);
+            Vec3::new(x, y, z(x, y))
+        }).collect();
+        mesh(positions, &[&[0, 1, 4, 3], &[1, 2, 5, 4], &[3, 4, 7, 6], &[4, 5, 8, 7]])
+    }
+
+    #[test]
+    fn smooths_a_tetrahedron() {
+        // Every vertex has three neighbours summing to minus itself, so the Loop weights
+        // scale the old vertices and the edge midpoints by exactly a quarter.
+        let corners = vec![Vec3::new(1.0, 1.0, 1.0), Vec3::new(1.0, -1.0, -1.0), Vec3::new(-1.0, 1.0, -1.0), Vec3::new(-1.0, -1.0, 1.0)];
+        let tetrahedron = mesh(corners.clone(), &[&[0, 1, 2], &[0, 3, 1], &[0, 2, 3], &[1, 3, 2]]);
+        let smooth = loop_subdivide(&tetrahedron, 1);
+        assert_eq!(smooth.positions.len(), 10);
+        assert_eq!(smooth.faces.len(), 16);
+        for (i, &p) in corners.iter().enumerate() {
+            assert!(close(smooth.positions[i], 0.25 * p));
+        }
+        let mut midpoints: Vec<Vec3> = smooth.positions[4..].to_vec();
+        for a in 0..4 {
+            for b in a + 1..4 {
+                let expected = 0.25 * (corners[a] + corners[b]);
+                let found = midpoints.iter().position(|&m| close(m, expected)).expect("missing edge point");
+                midpoints.swap_remove(found);
+            }
+        }
+    }
+
+    #[test]
+    fn creases_stay_straight() {
+        // A tent whose ridge runs along y = 1. Smoothing rounds it off unless it is a crease.
+        let tent = grid(|_, y| 1.0 - (y - 1.0).abs());
+        let rounded = loop_subdivide(&tent, 2);
+        assert!(rounded.positions.iter().all(|p| p.z() < 1.0 - 1e-3));
+
+        let mut creased = tent;
+        creased.add_crease(3, 4);
+        creased.add_crease(4, 5);
+        let sharp = loop_subdivide(&creased, 2);
+        let ridge: HashSet<usize> = sharp.creases.iter().flat_map(|&(a, b)| vec![a, b]).collect();
+        assert_eq!(ridge.len(), 9);
+        for &v in ridge.iter() {
+            let p = sharp.positions[v];
+            assert!((p.y() - 1.0).abs() < 1e-5 && (p.z() - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn boundaries_ignore_the_interior() {
+        // Lifting the middle vertex bends the surface but leaves the flat border alone.
+        let bump = grid(|x, y| if x == 1.0 && y == 1.0 { 1.0 } else { 0.0 });
+        let smooth = loop_subdivide(&bump, 1);
+        let mut uses: HashMap<(usize, usize), usize> = HashMap::new();
+        for face in smooth.faces.iter() {
+            for k in 0..3 {
+                *uses.entry(edge_key(face.vertices[k], face.vertices[(k + 1) % 3])).or_insert(0) += 1;
+            }
+        }
+        let boundary: HashSet<usize> = uses.iter().filter(|(_, &n)| n == 1).flat_map(|(&(a, b), _)| vec![a, b]).collect();
+        assert_eq!(boundary.len(), 16);
+        for &v in boundary.iter() {
+            assert!(smooth.positions[v].z().abs() < 1e-6);
+        }
+        assert!(smooth.positions.iter().any(|p| p.z() > 0.1));
+    }
+}
@@ -0,0 +1,159 @@
+extern crate rand;
+use rand::Rng;
+
+use std::f32;
+
+use crate::math::onb::Onb;
+use crate::math::ray::Ray;
+use crate::math::vec3::*;
+use super::hitable::HitRecord;
+use super::materials::*;
+
+/// Homogeneous participating medium, with coefficients per unit distance and per channel.
+#[derive(Debug, Copy, Clone)]
+pub struct Medium {
+    sigma_s: Vec3,
+    sigma_a: Vec3,
+    /// Henyey-Greenstein asymmetry: 0 scatters evenly, towards 1 mostly forwards.
+    g: f32
+}
+
+impl Medium {
+    pub fn new(sigma_s: Vec3, sigma_a: Vec3) -> Self {
+        Medium {
+            sigma_s,
+            sigma_a,
+            g: 0.0
+        }
+    }
+
+    /// Medium where light travels `mean_free_path` on average between events, of which an
+    /// `albedo` fraction scatter rather than absorb. Longer paths in red give skin its glow.
+    pub fn from_albedo(albedo: Vec3, mean_free_path: Vec3) -> Self {
+        let sigma_t = Vec3::new(1.0 / mean_free_path.x(), 1.0 / mean_free_path.y(), 1.0 / mean_free_path.z());
+        Medium::new(albedo * sigma_t, (Vec3::one() - albedo) * sigma_t)
+    }
+
+    pub fn with_anisotropy(mut self, g: f32) -> Self {
+        self.g = g.clamp(-0.99, 0.99);
+        self
+    }
+
+    fn transmittance(&self, distance: f32) -> Vec3 {
+        let sigma_t = self.sigma_s + self.sigma_a;
+        let channel = |s: f32| if s > 0.0 { (-s * distance).exp() } else { 1.0 };
+        Vec3::new(channel(sigma_t.x()), channel(sigma_t.y()), channel(sigma_t.z()))
+    }
+
+    /// Flies `ray` through the medium towards the surface `t_max` along it. Returns the ray
+    /// leaving a scattering event on the way, or `None` if it reaches the surface. Either way
+    /// `throughput` is updated. Distances are sampled in one channel, picked in proportion to
+    /// the throughput so far, and weighted by the density averaged the same way over all of
+    /// them, which keeps colored media from turning into colored noise on long walks.
+    pub fn sample(&self, ray: &Ray, t_max: f32, throughput: &mut Vec3) -> Option<Ray> {
+        let mut rng = rand::thread_rng();
+        let total = throughput.x() + throughput.y() + throughput.z();
+        let weights = if total > 0.0 { *throughput / total } else { Vec3::new(1.0, 1.0, 1.0) / 3.0 };
+        let u = rng.gen::<f32>();
+        let channel = if u < weights.x() { 0 } else if u < weights.x() + weights.y() { 1 } else { 2 };
+        let length = ray.direction().length();
+        let sigma_t = self.sigma_s + self.sigma_a;
+        let distance = if sigma_t[channel] > 0.0 { -(1.0 - rng.gen::<f32>()).ln() / sigma_t[channel] } else { f32::INFINITY };
+        if distance < t_max * length {
+            let transmittance = self.transmittance(distance);
+            let pdf = dot(weights, sigma_t * transmittance);
+            *throughput = *throughput * self.sigma_s * transmittance / pdf;
+            let direction = unit_vector(ray.direction());
+            Some(Ray::new(ray.point_at_parameter(distance / length), Onb::from_w(direction).local(self.sample_phase())))
+        }
+        else {
+            let transmittance = self.transmittance(t_max * length);
+            let probability = dot(weights, transmittance);
+            if probability > 0.0 {
+                *throughput = *throughput * transmittance / probability;
+            }
+            None
+        }
+    }
+
+    /// Henyey-Greenstein direction around +z, the direction of travel.
+    fn sample_phase(&self) -> Vec3 {
+        let mut rng = rand::thread_rng();
+        let (u1, u2) = (rng.gen::<f32>(), rng.gen::<f32>());
+        let cos_theta = if self.g.abs() < 1e-3 {
+            1.0 - 2.0 * u1
+        }
+        else {
+            let s = (1.0 - self.g * self.g) / (1.0 - self.g + 2.0 * self.g * u1);
+            (1.0 + self.g * self.g - s * s) / (2.0 * self.g)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * f32::consts::PI * u2;
+        Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+    }
+}
+
+/// Random walk subsurface scattering, for skin, wax, marble and milk. The closed surface is a
+/// smooth dielectric boundary on the way in; inside, paths wander through `medium` until they
+/// reach the surface again and leave it diffusely, where light sampling can pick them up.
+/// Only the path tracers that follow `Material::medium` (naive, NEE and MIS) see the interior.
+pub struct Subsurface {
+    refraction_index: f32,
+    medium: Medium
+}
+
+impl Subsurface {
+    pub fn new(refraction_index: f32, medium: Medium) -> Self {
+        Subsurface {
+            refraction_index,
+            medium
+        }
+    }
+}
+
+impl Material for Subsurface {
+    fn sample(&self, ray_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let unit_direction = unit_vector(ray_in.direction());
+        let cosine = -dot(unit_direction, rec.normal);
+        if cosine < 0.0 {
+            // Leaving: cosine distributed about the outward normal.
+            let scattered = Ray::new(rec.p, Onb::from_w(rec.normal).local(random_cosine_direction()));
+            let pdf = dot(scattered.direction(), rec.normal) / f32::consts::PI;
+            return Some(ScatterRecord {
+                attenuation: Vec3::one(),
+                scattered,
+                pdf,
+                is_specular: false
+            });
+        }
+        let mut rng = rand::thread_rng();
+        match refract(unit_direction, rec.normal, 1.0 / self.refraction_index) {
+            Some(refracted) if rng.gen::<f32>() >= shclick(cosine, self.refraction_index) => {
+                Some(ScatterRecord::specular(Vec3::one(), Ray::new(rec.p, refracted)))
+            },
+            _ => Some(ScatterRecord::specular(Vec3::one(), Ray::new(rec.p, reflect(unit_direction, rec.normal))))
+        }
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> Vec3 {
+        if dot(wo, rec.normal) < 0.0 && dot(wi, rec.normal) > 0.0 {
+            Vec3::one() / f32::consts::PI
+        }
+        else {
+            Vec3::zero()
+        }
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> f32 {
+        if dot(wo, rec.normal) < 0.0 {
+            dot(wi, rec.normal).max(0.0) / f32::consts::PI
+        }
+        else {
+            0.0
+        }
+    }
+
+    fn medium(&self) -> Option<Medium> {
+        Some(self.medium)
+    }
+}
@@ -0,0 +1,514 @@
+extern crate image;
+
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::math::noise::Perlin;
+use crate::math::vec3::Vec3;
+
+pub trait Texture: Send+Sync {
+    fn value(&self, u: f32, v: f32, p: Vec3) -> Vec3;
+
+    /// Single number reading, the average of the channels; used for heights.
+    fn scalar(&self, u: f32, v: f32, p: Vec3) -> f32 {
+        let c = self.value(u, v, p);
+        (c.x() + c.y() + c.z()) / 3.0
+    }
+}
+
+pub struct SolidColor {
+    color: Vec3
+}
+
+impl SolidColor {
+    pub fn new(color: Vec3) -> Self {
+        SolidColor {
+            color
+        }
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f32, _v: f32, _p: Vec3) -> Vec3 {
+        self.color
+    }
+}
+
+/// 3D checkerboard in world space, so it also works on surfaces without uvs.
+pub struct Checker {
+    even: Arc<dyn Texture>,
+    odd: Arc<dyn Texture>,
+    scale: f32
+}
+
+impl Checker {
+    pub fn new(even: Arc<dyn Texture>, odd: Arc<dyn Texture>, scale: f32) -> Self {
+        Checker {
+            even,
+            odd,
+            scale
+        }
+    }
+
+    pub fn from_colors(even: Vec3, odd: Vec3, scale: f32) -> Self {
+        Checker::new(Arc::new(SolidColor::new(even)), Arc::new(SolidColor::new(odd)), scale)
+    }
+}
+
+impl Texture for Checker {
+    fn value(&self, u: f32, v: f32, p: Vec3) -> Vec3 {
+        let inv = 1.0 / self.scale;
+        let sum = (p.x() * inv).floor() as i64 + (p.y() * inv).floor() as i64 + (p.z() * inv).floor() as i64;
+        if sum % 2 == 0 {
+            self.even.value(u, v, p)
+        }
+        else {
+            self.odd.value(u, v, p)
+        }
+    }
+}
+
+fn lerp(a: Vec3, b: Vec3, t: f32) -> Vec3 {
+    (1.0 - t) * a + t * b
+}
+
+/// Veined stone: a sine band along z, distorted by turbulence.
+pub struct Marble {
+    noise: Perlin,
+    base: Vec3,
+    vein: Vec3,
+    scale: f32
+}
+
+impl Marble {
+    pub fn new(base: Vec3, vein: Vec3, scale: f32) -> Self {
+        Marble {
+            noise: Perlin::default(),
+            base,
+            vein,
+            scale
+        }
+    }
+
+    /// Varies the pattern; the default seed is 0.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.noise = Perlin::new(seed);
+        self
+    }
+}
+
+impl Texture for Marble {
+    fn value(&self, _u: f32, _v: f32, p: Vec3) -> Vec3 {
+        let phase = self.scale * p.z() + 10.0 * self.noise.turbulence(self.scale * p, 7);
+        lerp(self.vein, self.base, 0.5 * (1.0 + phase.sin()))
+    }
+}
+
+/// Growth rings around the y axis, wobbled by low frequency noise.
+pub struct Wood {
+    noise: Perlin,
+    light: Vec3,
+    dark: Vec3,
+    ring_frequency: f32
+}
+
+impl Wood {
+    pub fn new(light: Vec3, dark: Vec3, ring_frequency: f32) -> Self {
+        Wood {
+            noise: Perlin::default(),
+            light,
+            dark,
+            ring_frequency
+        }
+    }
+
+    /// Varies the pattern; the default seed is 0.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.noise = Perlin::new(seed);
+        self
+    }
+}
+
+impl Texture for Wood {
+    fn value(&self, _u: f32, _v: f32, p: Vec3) -> Vec3 {
+        let radius = (p.x() * p.x() + p.z() * p.z()).sqrt();
+        let rings = self.ring_frequency * radius + 2.0 * self.noise.fbm(p, 4);
+        let t = rings - rings.floor();
+        // Sharpen the rings so the late wood reads as thin dark bands.
+        lerp(self.light, self.dark, t.powi(4))
+    }
+}
+
+/// Soft cumulus cover from thresholded fBm.
+pub struct Clouds {
+    noise: Perlin,
+    sky: Vec3,
+    cloud: Vec3,
+    scale: f32,
+    coverage: f32
+}
+
+impl Clouds {
+    pub fn new(sky: Vec3, cloud: Vec3, scale: f32, coverage: f32) -> Self {
+        Clouds {
+            noise: Perlin::default(),
+            sky,
+            cloud,
+            scale,
+            coverage
+        }
+    }
+
+    /// Varies the pattern; the default seed is 0.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.noise = Perlin::new(seed);
+        self
+    }
+}
+
+impl Texture for Clouds {
+    fn value(&self, _u: f32, _v: f32, p: Vec3) -> Vec3 {
+        let density = 0.5 + 0.5 * self.noise.fbm(self.scale * p, 6) - (1.0 - self.coverage);
+        lerp(self.sky, self.cloud, (2.0 * density).clamp(0.0, 1.0))
+    }
+}
+
+/// Voronoi cells, like cobbles or cracked mud: `cell` inside and `border` where the nearest two
+/// of the randomly placed feature points are about equally close.
+pub struct Cells {
+    noise: Perlin,
+    cell: Vec3,
+    border: Vec3,
+    scale: f32,
+    /// Difference in distance to the two nearest feature points, in cell sizes, over which
+    /// the border fades into the cell.
+    width: f32
+}
+
+impl Cells {
+    pub fn new(cell: Vec3, border: Vec3, scale: f32, width: f32) -> Self {
+        Cells {
+            noise: Perlin::default(),
+            cell,
+            border,
+            scale,
+            width
+        }
+    }
+
+    /// Varies the pattern; the default seed is 0.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.noise = Perlin::new(seed);
+        self
+    }
+}
+
+impl Texture for Cells {
+    fn value(&self, _u: f32, _v: f32, p: Vec3) -> Vec3 {
+        let (f1, f2) = self.noise.worley(self.scale * p);
+        lerp(self.border, self.cell, ((f2 - f1) / self.width.max(1e-6)).min(1.0))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WrapMode {
+    Repeat,
+    Mirror,
+    Clamp,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+}
+
+fn wrap(i: i64, size: usize, mode: WrapMode) -> usize {
+    let n = size as i64;
+    let wrapped = match mode {
+        WrapMode::Repeat => i.rem_euclid(n),
+        WrapMode::Mirror => {
+            let period = i.rem_euclid(2 * n);
+            if period < n { period } else { 2 * n - 1 - period }
+        },
+        WrapMode::Clamp => i.clamp(0, n - 1),
+    };
+    wrapped as usize
+}
+
+/// Texels are stored display encoded in files; decode with the gamma 2 curve the film writes.
+fn decode(c: u8) -> f32 {
+    let c = c as f32 / 255.0;
+    c * c
+}
+
+/// Texels of data images, like normal and height maps, are used as stored.
+fn raw(c: u8) -> f32 {
+    c as f32 / 255.0
+}
+
+/// Image lookup with (0, 0) at the bottom left, matching the sphere uvs.
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    texels: Vec<Vec3>,
+    wrap: WrapMode,
+    filter: Filter
+}
+
+impl ImageTexture {
+    pub fn new(width: usize, height: usize, texels: Vec<Vec3>) -> Self {
+        assert_eq!(width * height, texels.len());
+        ImageTexture {
+            width,
+            height,
+            texels,
+            wrap: WrapMode::Repeat,
+            filter: Filter::Bilinear
+        }
+    }
+
+    /// Loads PPM (P3/P6) directly, and PNG or JPEG through the `image` crate.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<ImageTexture> {
+        ImageTexture::load_with(path.as_ref(), decode)
+    }
+
+    /// Like `load`, but without decoding the texels, for images holding normals or heights.
+    pub fn load_data<P: AsRef<Path>>(path: P) -> io::Result<ImageTexture> {
+        ImageTexture::load_with(path.as_ref(), raw)
+    }
+
+    /// Grey image of the alpha channel, for cutouts. Images without one, PPM included, are
+    /// taken to hold the opacity themselves and load as with `load_data`.
+    pub fn load_alpha<P: AsRef<Path>>(path: P) -> io::Result<ImageTexture> {
+        let path = path.as_ref();
+        let extension = path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase());
+        if extension.as_deref() == Some("ppm") {
+            return ImageTexture::load_data(path);
+        }
+        let image = image::open(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        if !image.color().has_alpha() {
+            return ImageTexture::load_data(path);
+        }
+        let image = image.to_rgba8();
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let texels = image.pixels().map(|p| {
+            let a = raw(p[3]);
+            Vec3::new(a, a, a)
+        }).collect();
+        Ok(ImageTexture::new(width, height, texels))
+    }
+
+    fn load_with(path: &Path, decode: fn(u8) -> f32) -> io::Result<ImageTexture> {
+        let extension = path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase());
+        if extension.as_deref() == Some("ppm") {
+            return read_ppm(BufReader::new(File::open(path)?), decode);
+        }
+        let image = image::open(path)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?
+            .to_rgb8();
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let texels = image.pixels().map(|p| Vec3::new(decode(p[0]), decode(p[1]), decode(p[2]))).collect();
+        Ok(ImageTexture::new(width, height, texels))
+    }
+
+    pub fn with_wrap(mut self, wrap: WrapMode) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Vec3 {
+        let x = wrap(x, self.width, self.wrap);
+        let y = wrap(y, self.height, self.wrap);
+        self.texels[(self.height - 1 - y) * self.width + x]
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f32, v: f32, _p: Vec3) -> Vec3 {
+        if self.texels.is_empty() {
+            return Vec3::new(0.0, 1.0, 1.0);
+        }
+        let x = u * self.width as f32 - 0.5;
+        let y = v * self.height as f32 - 0.5;
+        match self.filter {
+            Filter::Nearest => self.texel(x.round() as i64, y.round() as i64),
+            Filter::Bilinear => {
+                let x0 = x.floor();
+                let y0 = y.floor();
+                let (fx, fy) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+                (1.0 - fx) * (1.0 - fy) * self.texel(x0, y0)
+                    + fx * (1.0 - fy) * self.texel(x0 + 1, y0)
+                    + (1.0 - fx) * fy * self.texel(x0, y0 + 1)
+                    + fx * fy * self.texel(x0 + 1, y0 + 1)
+            }
+        }
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_ppm<R: BufRead>(mut reader: R, decode: fn(u8) -> f32) -> io::Result<ImageTexture> {
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+
+    // Header tokens are whitespace separated and may be interleaved with # comments.
+    let mut pos = 0;
+    let mut header = vec![];
+    while header.len() < 4 {
+        while pos < bytes.len() && (bytes[pos].is_ascii_whitespace() || bytes[pos] == b'#') {
+            if bytes[pos] == b'#' {
+                while pos < bytes.len() && bytes[pos] != b'\n' {
+                    pos += 1;
+                }
+            }
+            else {
+                pos += 1;
+            }
+        }
+        let start = pos;
+        while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            return Err(invalid_data("truncated ppm header"));
+        }
+        header.push(String::from_utf8_lossy(&bytes[start..pos]).to_string());
+    }
+
+    let number = |s: &str| s.parse::<usize>().map_err(|_| invalid_data("bad ppm header"));
+    let (width, height, max) = (number(&header[1])?, number(&header[2])?, number(&header[3])?);
+    if max == 0 || max > 255 {
+        return Err(invalid_data("only 8 bit ppm files are supported"));
+    }
+    if width == 0 || height == 0 {
+        return Err(invalid_data("empty ppm image"));
+    }
+    let count = width.checked_mul(height).and_then(|n| n.checked_mul(3)).ok_or_else(|| invalid_data("ppm image is too large"))?;
+    let scale = 255.0 / max as f32;
+    let channel = |c: usize| decode((c as f32 * scale).round().min(255.0) as u8);
+
+    // Every value takes at least one byte, so the data bounds what is worth allocating.
+    let data = &bytes[(pos + 1).min(bytes.len())..];
+    if data.len() < count {
+        return Err(invalid_data("truncated ppm data"));
+    }
+    let mut values = Vec::with_capacity(count);
+    match header[0].as_str() {
+        "P6" => values.extend(data[..count].iter().map(|&c| c as usize)),
+        "P3" => {
+            for token in String::from_utf8_lossy(&bytes[pos..]).split_whitespace().take(count) {
+                values.push(number(token)?);
+            }
+            if values.len() < count {
+                return Err(invalid_data("truncated ppm data"));
+            }
+        },
+        _ => return Err(invalid_data("unsupported ppm magic")),
+    }
+
+    let texels = values.chunks(3).map(|c| Vec3::new(channel(c[0]), channel(c[1]), channel(c[2]))).collect();
+    Ok(ImageTexture::new(width, height, texels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-5
+    }
+
+    #[test]
+    fn reads_ascii_ppm() {
+        // Rows run top to bottom in the file, so the first one is at v = 1.
+        let ppm = "P3\n# two by two, four bit\n2 2\n15\n15 0 0  0 15 0\n0 0 15  15 15 15\n";
+        let texture = read_ppm(ppm.as_bytes(), raw).unwrap().with_filter(Filter::Nearest);
+        assert!(close(texture.value(0.25, 0.75, Vec3::zero()), Vec3::new(1.0, 0.0, 0.0)));
+        assert!(close(texture.value(0.75, 0.75, Vec3::zero()), Vec3::new(0.0, 1.0, 0.0)));
+        assert!(close(texture.value(0.25, 0.25, Vec3::zero()), Vec3::new(0.0, 0.0, 1.0)));
+        assert!(close(texture.value(0.75, 0.25, Vec3::zero()), Vec3::one()));
+    }
+
+    #[test]
+    fn reads_binary_ppm() {
+        let mut ppm = b"P6 2 1 255\n".to_vec();
+        ppm.extend_from_slice(&[255, 128, 0, 0, 0, 255]);
+        let decoded = read_ppm(&ppm[..], decode).unwrap().with_filter(Filter::Nearest);
+        let half = 128.0 / 255.0;
+        assert!(close(decoded.value(0.25, 0.5, Vec3::zero()), Vec3::new(1.0, half * half, 0.0)));
+        let data = read_ppm(&ppm[..], raw).unwrap().with_filter(Filter::Nearest);
+        assert!(close(data.value(0.25, 0.5, Vec3::zero()), Vec3::new(1.0, half, 0.0)));
+        assert!(close(data.value(0.75, 0.5, Vec3::zero()), Vec3::new(0.0, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn rejects_malformed_ppm() {
+        let error = |ppm: &[u8]| read_ppm(ppm, raw).err().unwrap().to_string();
+        assert_eq!(error(b"P3 1 1"), "truncated ppm header");
+        assert_eq!(error(b"P3 1 x 255 0 0 0"), "bad ppm header");
+        assert_eq!(error(b"P3 1 1 65535 0 0 0"), "only 8 bit ppm files are supported");
+        assert_eq!(error(b"P3 2 1 255 0 0 0"), "truncated ppm data");
+        assert_eq!(error(b"P6 2 1 255\n\x00\x00\x00"), "truncated ppm data");
+        assert_eq!(error(b"P5 1 1 255\n\x00\x00\x00"), "unsupported ppm magic");
+        assert_eq!(error(b"P3 0 0 255"), "empty ppm image");
+        assert_eq!(error(b"P6 1 0 255\n"), "empty ppm image");
+        assert_eq!(error(b"P6 18446744073709551615 2 255\n\x00"), "ppm image is too large");
+        assert_eq!(error(b"P6 100000 100000 255\n\x00\x00\x00"), "truncated ppm data");
+        assert_eq!(error(b"P3 100000 100000 255 0 0 0"), "truncated ppm data");
+    }
+
+    #[test]
+    fn wraps_and_filters() {
+        let texels = vec![Vec3::zero(), Vec3::one()];
+        let repeat = ImageTexture::new(2, 1, texels.clone()).with_filter(Filter::Nearest);
+        assert!(close(repeat.value(1.25, 0.5, Vec3::zero()), Vec3::zero()));
+        let clamp = ImageTexture::new(2, 1, texels.clone()).with_filter(Filter::Nearest).with_wrap(WrapMode::Clamp);
+        assert!(close(clamp.value(1.25, 0.5, Vec3::zero()), Vec3::one()));
+        let mirror = ImageTexture::new(2, 1, texels.clone()).with_filter(Filter::Nearest).with_wrap(WrapMode::Mirror);
+        assert!(close(mirror.value(1.25, 0.5, Vec3::zero()), Vec3::one()));
+        // Halfway between the texel centers.
+        let bilinear = ImageTexture::new(2, 1, texels).with_wrap(WrapMode::Clamp);
+        assert!(close(bilinear.value(0.5, 0.5, Vec3::zero()), Vec3::new(0.5, 0.5, 0.5)));
+    }
+
+    #[test]
+    fn checkers_alternate_in_world_space() {
+        let checker = Checker::from_colors(Vec3::zero(), Vec3::one(), 0.5);
+        assert!(close(checker.value(0.0, 0.0, Vec3::new(0.25, 0.25, 0.25)), Vec3::zero()));
+        assert!(close(checker.value(0.0, 0.0, Vec3::new(0.75, 0.25, 0.25)), Vec3::one()));
+        assert!(close(checker.value(0.0, 0.0, Vec3::new(-0.25, 0.25, 0.25)), Vec3::one()));
+    }
+
+    #[test]
+    fn loads_images_and_their_alpha() {
+        let dir = std::env::temp_dir().join(format!("texture_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let png = dir.join("pixel.png");
+        image::RgbaImage::from_raw(1, 1, vec![255, 128, 0, 64]).unwrap().save(&png).unwrap();
+        let half = 128.0 / 255.0;
+        let alpha = 64.0 / 255.0;
+        assert!(close(ImageTexture::load(&png).unwrap().value(0.5, 0.5, Vec3::zero()), Vec3::new(1.0, half * half, 0.0)));
+        assert!(close(ImageTexture::load_data(&png).unwrap().value(0.5, 0.5, Vec3::zero()), Vec3::new(1.0, half, 0.0)));
+        assert!(close(ImageTexture::load_alpha(&png).unwrap().value(0.5, 0.5, Vec3::zero()), Vec3::new(alpha, alpha, alpha)));
+
+        // PPM files have no alpha, so they hold the opacity themselves.
+        let ppm = dir.join("pixel.ppm");
+        std::fs::write(&ppm, "P3 1 1 255 0 128 255\n").unwrap();
+        assert!(close(ImageTexture::load_alpha(&ppm).unwrap().value(0.5, 0.5, Vec3::zero()), Vec3::new(0.0, half, 1.0)));
+        assert!(ImageTexture::load(dir.join("missing.png")).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,125 @@
+use std::f32;
+
+use crate::math::ray::Ray;
+use crate::math::spectrum::*;
+use crate::math::vec3::Vec3;
+
+/// Angles a `FilmTable` holds colors for, evenly spaced in cosine.
+const TABLE_SIZE: usize = 128;
+
+/// What a `ThinFilm` is coated onto.
+#[derive(Debug, Copy, Clone)]
+pub enum Substrate {
+    /// A dielectric with the given index of refraction.
+    Dielectric(f32),
+    /// A metal, given by its color at normal incidence. Its phase shift is taken to be half a
+    /// wave, as for a very dense dielectric.
+    Metal(Vec3),
+}
+
+/// Transparent layer a few hundred nanometres thick, like a soap film, an oil slick or an
+/// anti-reflection coating. Light reflected off its top interferes with light reflected off
+/// the surface below, so which colors come back depends on the thickness and the angle.
+#[derive(Debug, Copy, Clone)]
+pub struct ThinFilm {
+    /// In nanometres.
+    thickness: f32,
+    ior: f32,
+}
+
+impl ThinFilm {
+    pub fn new(thickness: f32, ior: f32) -> Self {
+        ThinFilm {
+            thickness,
+            ior
+        }
+    }
+
+    /// Reflectance of `substrate` under the film for light arriving from outside at `cos_theta`
+    /// to the normal. Along a ray with a wavelength that is the reflectance at that wavelength,
+    /// in every channel; otherwise the color of the whole reflectance spectrum.
+    pub fn reflectance(&self, ray_in: &Ray, cos_theta: f32, substrate: Substrate) -> Vec3 {
+        match ray_in.wavelength() {
+            Some(lambda) => {
+                let r = self.reflectance_at(cos_theta, substrate, lambda);
+                Vec3::new(r, r, r)
+            },
+            None => self.color(cos_theta, substrate)
+        }
+    }
+
+    /// Color of the whole reflectance spectrum at `cos_theta`. This integrates over the visible
+    /// range, so materials over a fixed substrate should look it up in a `FilmTable` instead.
+    pub fn color(&self, cos_theta: f32, substrate: Substrate) -> Vec3 {
+        let rgb = reflectance_to_rgb(|lambda| self.reflectance_at(cos_theta, substrate, lambda));
+        Vec3::new(rgb.x().clamp(0.0, 1.0), rgb.y().clamp(0.0, 1.0), rgb.z().clamp(0.0, 1.0))
+    }
+
+    /// Airy sum over all the bounces inside the film at `lambda` nanometres, averaged over both
+    /// polarizations. Neither the film nor a dielectric substrate absorb.
+    pub fn reflectance_at(&self, cos_theta: f32, substrate: Substrate, lambda: f32) -> f32 {
+        let cos_outside = cos_theta.clamp(0.0, 1.0);
+        let sin2_outside = 1.0 - cos_outside * cos_outside;
+        let n = self.ior;
+        let cos_film = (1.0 - sin2_outside / (n * n)).max(0.0).sqrt();
+        let (below_s, below_p) = match substrate {
+            Substrate::Dielectric(n_below) => {
+                let sin2_below = sin2_outside / (n_below * n_below);
+                if sin2_below >= 1.0 {
+                    return 1.0;
+                }
+                let cos_below = (1.0 - sin2_below).sqrt();
+                (fresnel_s(n, cos_film, n_below, cos_below), fresnel_p(n, cos_film, n_below, cos_below))
+            },
+            Substrate::Metal(color) => {
+                let f0 = uplift_at(color, lambda).clamp(0.0, 1.0);
+                let r = f0 + (1.0 - f0) * (1.0 - cos_film).powi(5);
+                (-r.sqrt(), -r.sqrt())
+            }
+        };
+        let above_s = fresnel_s(1.0, cos_outside, n, cos_film);
+        let above_p = fresnel_p(1.0, cos_outside, n, cos_film);
+        // Extra phase of the light that crossed the film twice.
+        let phase = 4.0 * f32::consts::PI * n * self.thickness * cos_film / lambda;
+        0.5 * (airy(above_s, below_s, phase) + airy(above_p, below_p, phase))
+    }
+}
+
+/// `ThinFilm::color` over one substrate, tabulated by angle when the material is made.
+#[derive(Debug, Clone)]
+pub struct FilmTable {
+    colors: Vec<Vec3>,
+}
+
+impl FilmTable {
+    pub fn new(film: ThinFilm, substrate: Substrate) -> Self {
+        FilmTable {
+            colors: (0..TABLE_SIZE).map(|i| film.color(i as f32 / (TABLE_SIZE - 1) as f32, substrate)).collect()
+        }
+    }
+
+    /// `ThinFilm::color` at `cos_theta`, interpolated between the nearest angles in the table.
+    pub fn color(&self, cos_theta: f32) -> Vec3 {
+        let x = cos_theta.clamp(0.0, 1.0) * (TABLE_SIZE - 1) as f32;
+        let i = (x as usize).min(TABLE_SIZE - 2);
+        let t = x - i as f32;
+        (1.0 - t) * self.colors[i] + t * self.colors[i + 1]
+    }
+}
+
+/// Fresnel amplitude reflection coefficient for s polarized light going from index `n1` to `n2`.
+fn fresnel_s(n1: f32, cos1: f32, n2: f32, cos2: f32) -> f32 {
+    (n1 * cos1 - n2 * cos2) / (n1 * cos1 + n2 * cos2)
+}
+
+/// Fresnel amplitude reflection coefficient for p polarized light going from index `n1` to `n2`.
+fn fresnel_p(n1: f32, cos1: f32, n2: f32, cos2: f32) -> f32 {
+    (n2 * cos1 - n1 * cos2) / (n2 * cos1 + n1 * cos2)
+}
+
+/// Reflectance of a film with amplitude coefficients `above` and `below` at its two faces.
+fn airy(above: f32, below: f32, phase: f32) -> f32 {
+    let cross = 2.0 * above * below * phase.cos();
+    let r = (above * above + below * below + cross) / (1.0 + above * above * below * below + cross);
+    r.clamp(0.0, 1.0)
+}